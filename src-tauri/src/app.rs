@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use once_cell::sync::{Lazy, OnceCell};
+use tracing::{info, warn};
+
+use crate::commands;
+use crate::utils;
+use crate::utils::erabooru::ClientCache;
+use crate::utils::hash_index::HashIndex;
+use crate::utils::history::History;
+use crate::utils::queue::Queue;
+use crate::utils::watch_stats::WatchStats;
+use crate::utils::watcher::WatcherHandle;
+use crate::utils::worker::{self, WorkerPool};
+
+use tauri::{
+  menu::{Menu, MenuItem},
+  tray::{TrayIconBuilder, TrayIconEvent, MouseButton, MouseButtonState},
+  Manager, WindowEvent
+};
+
+pub(crate) struct AppState {
+    pub(crate) watchers: Mutex<HashMap<String, WatcherHandle>>,
+    pub(crate) queue: OnceCell<Arc<Queue>>,
+    pub(crate) hashes: OnceCell<Arc<HashIndex>>,
+    pub(crate) history: OnceCell<Arc<History>>,
+    pub(crate) workers: Mutex<Option<WorkerPool>>,
+    pub(crate) clients: Arc<ClientCache>,
+    pub(crate) watch_stats: Arc<WatchStats>,
+}
+
+impl AppState {
+    pub(crate) fn queue(&self) -> Result<Arc<Queue>, String> {
+        self.queue
+            .get()
+            .cloned()
+            .ok_or_else(|| "upload queue not initialized".to_string())
+    }
+
+    pub(crate) fn hashes(&self) -> Result<Arc<HashIndex>, String> {
+        self.hashes
+            .get()
+            .cloned()
+            .ok_or_else(|| "hash index not initialized".to_string())
+    }
+
+    pub(crate) fn history(&self) -> Result<Arc<History>, String> {
+        self.history
+            .get()
+            .cloned()
+            .ok_or_else(|| "upload history not initialized".to_string())
+    }
+}
+
+pub(crate) static STATE: Lazy<AppState> = Lazy::new(|| AppState {
+    watchers: Mutex::new(HashMap::new()),
+    queue: OnceCell::new(),
+    hashes: OnceCell::new(),
+    history: OnceCell::new(),
+    workers: Mutex::new(None),
+    clients: Arc::new(ClientCache::default()),
+    watch_stats: Arc::new(WatchStats::default()),
+});
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    tauri::Builder::default()
+        .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_store::Builder::default().build())
+        .invoke_handler(tauri::generate_handler![
+            commands::settings::save_settings,
+            commands::settings::load_settings,
+            commands::watching::start_watching,
+            commands::watching::stop_watching,
+            commands::watching::start_target,
+            commands::watching::stop_target,
+            commands::watching::watcher_status,
+            commands::uploading::scan_folder,
+            commands::uploading::upload_folder,
+            commands::uploading::pause_upload,
+            commands::uploading::resume_upload,
+            commands::uploading::cancel_upload,
+            commands::uploading::upload_job,
+            commands::uploading::upload_jobs,
+            commands::queue::queue_snapshot,
+            commands::queue::failed_uploads,
+            commands::queue::retry_failed,
+            commands::history::query_history,
+            commands::history::export_history,
+            commands::logs::open_log,
+            commands::logs::tail_log,
+        ])
+        .setup(|app| {
+            /* -------- logging -------- */
+            let settings = utils::store::get_settings(app.handle())?;
+            let log_dir = app.path().app_log_dir()?;
+            utils::logging::init(&log_dir, settings.log_level, cfg!(debug_assertions))?;
+
+            /* -------- credentials -------- */
+            match utils::store::encrypt_plain_secrets(app.handle()) {
+                Ok(true) => info!("Moved saved credentials to encrypted storage"),
+                Ok(false) => {}
+                Err(e) => warn!("Failed to move saved credentials to encrypted storage: {}", e),
+            }
+
+            /* -------- upload queue -------- */
+            let queue_path = app.path().app_data_dir()?.join("queue.db");
+            let queue = Arc::new(Queue::open(&queue_path)?);
+            let resumed = queue.requeue_interrupted()?;
+            if resumed > 0 {
+                info!("Resuming {} interrupted uploads", resumed);
+            }
+            let _ = STATE.queue.set(queue.clone());
+
+            let hashes_path = app.path().app_data_dir()?.join("hash_index.db");
+            let hashes = Arc::new(HashIndex::open(&hashes_path)?);
+            let _ = STATE.hashes.set(hashes.clone());
+
+            let history_path = app.path().app_data_dir()?.join("history.db");
+            let history = Arc::new(History::open(&history_path)?);
+            let _ = STATE.history.set(history.clone());
+
+            let handle = Arc::new(app.handle().clone());
+            let mut workers = WorkerPool::new(worker::Context {
+                events: handle.clone(),
+                settings: handle,
+                queue,
+                clients: STATE.clients.clone(),
+                hashes,
+                history,
+                stats: STATE.watch_stats.clone(),
+            });
+            workers.resize(settings.upload_workers);
+            *STATE.workers.lock().unwrap() = Some(workers);
+
+            /* -------- tray menu -------- */
+            let show = MenuItem::with_id(app, "show", "Show", true, None::<&str>)?;
+            let retry = MenuItem::with_id(app, "retry_failed", "Retry Failed Uploads", true, None::<&str>)?;
+            let quit = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
+            let menu = Menu::with_items(app, &[&show, &retry, &quit])?;
+
+            /* -------- tray icon -------- */
+            TrayIconBuilder::new()
+                .menu(&menu)
+                .on_menu_event(|app, event| match event.id.as_ref() {
+                "show" => {
+                    if let Some(win) = app.get_webview_window("main") {
+                        let _ = win.show();
+                        let _ = win.set_focus();
+                    }
+                }
+                "retry_failed" => {
+                    if let Err(e) = commands::queue::retry_failed(None) {
+                        warn!("Failed to retry failed uploads: {}", e);
+                    }
+                }
+                "quit" => {
+                    if let Some(mut workers) = STATE.workers.lock().unwrap().take() {
+                        workers.resize(0);
+                    }
+                    app.exit(0)
+                }
+                _ => {}
+                })
+                .on_tray_icon_event(
+                    |app, event| if let TrayIconEvent::Click { 
+                            button: MouseButton::Left,
+                            button_state: MouseButtonState::Up,
+                            ..
+                         } = event {
+                        if let Some(win) = app.app_handle().get_webview_window("main") {
+                            let _ = win.show();
+                            let _ = win.set_focus();
+                        }
+                    }
+                )
+                .icon(app.default_window_icon().unwrap().clone())
+                .build(app)?;
+
+            Ok(())
+        })
+        .on_window_event(|window, event| {
+            if let WindowEvent::CloseRequested { api, .. } = event {
+                window.hide().unwrap();
+                api.prevent_close();
+            }
+        })
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
\ No newline at end of file
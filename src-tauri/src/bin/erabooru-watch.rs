@@ -0,0 +1,3 @@
+fn main() -> std::process::ExitCode {
+    erabooru_file_watcher_lib::cli::run()
+}
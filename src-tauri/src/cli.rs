@@ -0,0 +1,292 @@
+use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::info;
+
+use crate::utils::{
+    erabooru::{ClientCache, Error},
+    events::{EventSink, Progress, WatcherStatus},
+    hash_index::HashIndex,
+    history::History,
+    logging,
+    queue::{JobState, Queue},
+    store::Settings,
+    upload,
+    watch_stats::WatchStats,
+    watcher,
+    worker::{self, WorkerPool},
+};
+
+// Some uploads failed, or watching could not start
+const EXIT_FAILED: u8 = 1;
+// Invalid arguments or config, the same code clap uses for usage errors
+const EXIT_CONFIG: u8 = 2;
+// Stopped by a second interrupt before the uploads in progress finished
+const EXIT_INTERRUPTED: u8 = 130;
+
+// How often `upload` checks on its job
+const JOB_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Watches folders and uploads media to EraBooru without the desktop app.
+#[derive(Parser)]
+#[command(name = "erabooru-watch", version)]
+struct Cli {
+    /// Settings file, JSON if it ends in `.json`, TOML otherwise
+    #[arg(short, long, default_value = "erabooru-watch.toml")]
+    config: PathBuf,
+    /// Where the upload queue, hash index and history are kept
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Watch every enabled target until interrupted
+    Watch,
+    /// Upload every media file under a folder, then exit
+    Upload {
+        folder: PathBuf,
+        /// Target to upload to, needed when the config has more than one
+        #[arg(short, long)]
+        target: Option<String>,
+    },
+    /// Count the media files under a folder
+    Scan { folder: PathBuf },
+}
+
+/// Entry point of the `erabooru-watch` binary.
+pub fn run() -> ExitCode {
+    let cli = Cli::parse();
+    let data_dir = cli.data_dir.clone().unwrap_or_else(default_data_dir);
+    let result = match &cli.command {
+        Command::Watch => load_config(&cli.config).and_then(|s| watch(s, &data_dir)),
+        Command::Upload { folder, target } => {
+            load_config(&cli.config).and_then(|s| upload(s, &data_dir, folder, target.as_deref()))
+        }
+        Command::Scan { folder } => scan(folder),
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("erabooru-watch: {}", e);
+            match e {
+                Error::Config { .. } => ExitCode::from(EXIT_CONFIG),
+                _ => ExitCode::from(EXIT_FAILED),
+            }
+        }
+    }
+}
+
+/// Nothing listens for events, everything of interest is logged.
+struct Headless;
+
+impl EventSink for Headless {
+    fn progress(&self, _event: Progress) {}
+    fn watcher(&self, _event: WatcherStatus) {}
+}
+
+/// The logger, databases and upload workers shared by `watch` and `upload`.
+struct Runtime {
+    events: Arc<dyn EventSink>,
+    queue: Arc<Queue>,
+    hashes: Arc<HashIndex>,
+    stats: Arc<WatchStats>,
+    workers: WorkerPool,
+}
+
+impl Runtime {
+    fn start(settings: &Settings, data_dir: &Path) -> Result<Self, Error> {
+        logging::init(&data_dir.join("logs"), settings.log_level, true).map_err(Error::io)?;
+        let queue = Arc::new(Queue::open(&data_dir.join("queue.db")).map_err(Error::io)?);
+        let resumed = queue.requeue_interrupted().map_err(Error::io)?;
+        if resumed > 0 {
+            info!("Resuming {} interrupted uploads", resumed);
+        }
+        let hashes = Arc::new(HashIndex::open(&data_dir.join("hash_index.db")).map_err(Error::io)?);
+        let history = Arc::new(History::open(&data_dir.join("history.db")).map_err(Error::io)?);
+
+        let events: Arc<dyn EventSink> = Arc::new(Headless);
+        let stats = Arc::new(WatchStats::default());
+        let mut workers = WorkerPool::new(worker::Context {
+            events: events.clone(),
+            settings: Arc::new(settings.clone()),
+            queue: queue.clone(),
+            clients: Arc::new(ClientCache::default()),
+            hashes: hashes.clone(),
+            history,
+            stats: stats.clone(),
+        });
+        workers.resize(settings.upload_workers);
+        Ok(Runtime {
+            events,
+            queue,
+            hashes,
+            stats,
+            workers,
+        })
+    }
+
+    /// Files being uploaded are not waited for, they are picked up again
+    /// from the queue on the next run.
+    fn stop(mut self) {
+        self.workers.resize(0);
+    }
+}
+
+fn watch(settings: Settings, data_dir: &Path) -> Result<ExitCode, Error> {
+    let targets: Vec<_> = settings.targets.iter().filter(|t| t.enabled).cloned().collect();
+    if targets.is_empty() {
+        return Err(Error::config("no watch targets configured"));
+    }
+    let signals = signals()?;
+    let runtime = Runtime::start(&settings, data_dir)?;
+    let ctx = Arc::new(watcher::Context {
+        events: runtime.events.clone(),
+        queue: runtime.queue.clone(),
+        hashes: runtime.hashes.clone(),
+        stats: runtime.stats.clone(),
+    });
+
+    runtime.stats.reset();
+    let mut watchers = Vec::new();
+    for target in targets {
+        match watcher::spawn(ctx.clone(), target) {
+            Ok(w) => watchers.push(w),
+            Err(e) => {
+                watchers.into_iter().for_each(|w| w.stop());
+                runtime.stop();
+                return Err(e);
+            }
+        }
+    }
+
+    let _ = signals.recv();
+    info!("Stopping watchers");
+    watchers.into_iter().for_each(|w| w.stop());
+    let counters = runtime.stats.snapshot();
+    println!(
+        "{} files seen, {} uploaded, {} duplicates, {} failed",
+        counters.files_seen, counters.uploaded, counters.duplicates, counters.failures
+    );
+    runtime.stop();
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Uploads a folder as a job and waits for it. The first interrupt cancels
+/// the files not yet started and waits for the rest, a second one quits.
+fn upload(settings: Settings, data_dir: &Path, folder: &Path, target: Option<&str>) -> Result<ExitCode, Error> {
+    let target = match (target, settings.targets.as_slice()) {
+        (Some(id), _) => settings
+            .target(id)
+            .ok_or_else(|| Error::config(format!("unknown watch target: {}", id)))?,
+        (None, [only]) => only,
+        (None, []) => return Err(Error::config("no watch targets configured")),
+        (None, _) => return Err(Error::config("more than one target configured, pick one with --target")),
+    };
+    if target.server.is_empty() {
+        return Err(Error::config("server not set"));
+    }
+    let folder = upload::resolve_folder(folder)?;
+    let target = target.id.clone();
+
+    let signals = signals()?;
+    let runtime = Runtime::start(&settings, data_dir)?;
+    let job = runtime.queue.create_job(&folder, &target).map_err(Error::io)?;
+    info!("Starting upload job {} for {}", job, folder);
+    {
+        let (events, queue, hashes) = (runtime.events.clone(), runtime.queue.clone(), runtime.hashes.clone());
+        std::thread::spawn(move || upload::list_folder(&*events, &queue, &hashes, job, &folder, &target));
+    }
+
+    let mut cancelled = false;
+    let mut last = None;
+    let job = loop {
+        if signals.recv_timeout(JOB_POLL_INTERVAL).is_ok() {
+            if cancelled {
+                return Ok(ExitCode::from(EXIT_INTERRUPTED));
+            }
+            cancelled = true;
+            runtime.queue.set_job_state(job, JobState::Cancelled).map_err(Error::io)?;
+            println!("Cancelling, waiting for uploads in progress. Interrupt again to quit right away.");
+        }
+        let job = runtime
+            .queue
+            .job(job)
+            .map_err(Error::io)?
+            .ok_or_else(|| Error::io(format!("upload job {} disappeared", job)))?;
+        if !job.listing && job.remaining == 0 {
+            break job;
+        }
+        let counts = (job.uploaded, job.duplicate, job.failed, job.remaining);
+        if last != Some(counts) {
+            println!(
+                "{} uploaded, {} duplicates, {} failed, {} remaining",
+                job.uploaded, job.duplicate, job.failed, job.remaining
+            );
+            last = Some(counts);
+        }
+    };
+    runtime.stop();
+
+    println!(
+        "Done: {} uploaded, {} duplicates, {} skipped, {} failed, {} cancelled",
+        job.uploaded, job.duplicate, job.skipped, job.failed, job.cancelled
+    );
+    Ok(if job.failed > 0 {
+        ExitCode::from(EXIT_FAILED)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+fn scan(folder: &Path) -> Result<ExitCode, Error> {
+    if !folder.is_dir() {
+        return Err(Error::config(format!("not a folder: {}", folder.display())));
+    }
+    let (videos, images, size) = upload::scan_folder(folder)?;
+    println!(
+        "{} videos, {} images, {:.1} MiB",
+        videos,
+        images,
+        size as f64 / (1024.0 * 1024.0)
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Settings in the same shape the app stores them, credentials included.
+fn load_config(path: &Path) -> Result<Settings, Error> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| Error::config(format!("cannot read {}: {}", path.display(), e)))?;
+    let settings: Settings = if path.extension().is_some_and(|e| e == "json") {
+        serde_json::from_str(&text).map_err(|e| Error::config(format!("{}: {}", path.display(), e)))?
+    } else {
+        toml::from_str(&text).map_err(|e| Error::config(format!("{}: {}", path.display(), e)))?
+    };
+    settings.validate().map_err(Error::config)?;
+    Ok(settings)
+}
+
+fn default_data_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("XDG_DATA_HOME") {
+        return PathBuf::from(dir).join("erabooru-watch");
+    }
+    if let Some(home) = std::env::var_os("HOME") {
+        return PathBuf::from(home).join(".local/share/erabooru-watch");
+    }
+    PathBuf::from(".erabooru-watch")
+}
+
+/// Receives a message for every SIGINT, SIGTERM or SIGHUP.
+fn signals() -> Result<Receiver<()>, Error> {
+    let (tx, rx) = channel();
+    ctrlc::set_handler(move || {
+        let _ = tx.send(());
+    })
+    .map_err(|e| Error::Other { message: e.to_string() })?;
+    Ok(rx)
+}
@@ -0,0 +1,32 @@
+use serde::Deserialize;
+use std::fs::File;
+use std::io::BufWriter;
+use tracing::info;
+
+use crate::utils::history::{self, HistoryEntry, HistoryQuery};
+use crate::STATE;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+#[tauri::command]
+pub fn query_history(query: HistoryQuery) -> Result<Vec<HistoryEntry>, String> {
+    STATE.history()?.query(&query)
+}
+
+/// Writes the entries matching `query` to `path`, returns how many were written.
+#[tauri::command]
+pub fn export_history(query: HistoryQuery, format: ExportFormat, path: String) -> Result<usize, String> {
+    let entries = STATE.history()?.query(&query)?;
+    let out = BufWriter::new(File::create(&path).map_err(|e| e.to_string())?);
+    match format {
+        ExportFormat::Csv => history::write_csv(&entries, out)?,
+        ExportFormat::Json => serde_json::to_writer_pretty(out, &entries).map_err(|e| e.to_string())?,
+    }
+    info!("Exported {} history entries to {}", entries.len(), path);
+    Ok(entries.len())
+}
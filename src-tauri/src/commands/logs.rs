@@ -0,0 +1,23 @@
+use tauri::Manager;
+use tauri_plugin_opener::OpenerExt;
+
+use crate::utils::{self, erabooru::Error};
+
+const DEFAULT_TAIL_LINES: usize = 200;
+
+/// Opens the current log file in the system's default viewer.
+#[tauri::command]
+pub fn open_log(app: tauri::AppHandle) -> Result<(), Error> {
+    let dir = app.path().app_log_dir().map_err(Error::io)?;
+    let path = utils::logging::current_file(&dir).ok_or_else(|| Error::io("no log file yet"))?;
+    app.opener()
+        .open_path(path.to_string_lossy(), None::<&str>)
+        .map_err(Error::io)
+}
+
+/// The last lines of the current log file, oldest first.
+#[tauri::command]
+pub fn tail_log(app: tauri::AppHandle, lines: Option<usize>) -> Result<Vec<String>, Error> {
+    let dir = app.path().app_log_dir().map_err(Error::io)?;
+    utils::logging::tail(&dir, lines.unwrap_or(DEFAULT_TAIL_LINES)).map_err(Error::io)
+}
@@ -1,3 +1,6 @@
 pub mod settings;
 pub mod watching;
 pub mod uploading;
+pub mod queue;
+pub mod history;
+pub mod logs;
@@ -0,0 +1,25 @@
+use tracing::info;
+
+use crate::utils::queue::QueueItem;
+use crate::STATE;
+
+// Finished items included in a snapshot, on top of everything in flight
+const RECENT_ITEMS: u32 = 100;
+
+#[tauri::command]
+pub fn queue_snapshot() -> Result<Vec<QueueItem>, String> {
+    STATE.queue()?.snapshot(RECENT_ITEMS)
+}
+
+#[tauri::command]
+pub fn failed_uploads() -> Result<Vec<QueueItem>, String> {
+    STATE.queue()?.failed()
+}
+
+/// Queues failed uploads again, all of them when no ids are given.
+#[tauri::command]
+pub fn retry_failed(ids: Option<Vec<i64>>) -> Result<usize, String> {
+    let retried = STATE.queue()?.retry_failed(ids.as_deref())?;
+    info!("Retrying {} failed uploads", retried);
+    Ok(retried)
+}
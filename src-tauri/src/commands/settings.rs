@@ -1,36 +1,27 @@
 use tauri::AppHandle;
-use tauri_plugin_store::StoreExt;
+use tracing::{debug, info};
 use crate::utils;
+use crate::STATE;
 
 #[tauri::command]
 pub fn load_settings(app: AppHandle) -> Result<utils::store::Settings, String> {
-    println!("Loading settings");
+    debug!("Loading settings");
     utils::store::get_settings(&app)
 }
 
 #[tauri::command]
-pub fn save_settings(
-    app: AppHandle,
-    folder: String,
-    server: String,
-    auto_tags: Vec<utils::store::AutoTagRule>,
-    override_upload_date: bool,
-) -> Result<(), String> {
-    println!("Saving settings: folder = {}, server = {}", folder, server);
+pub fn save_settings(app: AppHandle, settings: utils::store::Settings) -> Result<(), String> {
+    info!("Saving settings: {} watch targets", settings.targets.len());
+    settings.validate()?;
 
-    let store = app.store("store.json").map_err(|e| e.to_string())?;
-    let settings = utils::store::Settings {
-        folder,
-        server,
-        auto_tags,
-        override_upload_date,
-    };
+    utils::store::save_settings(&app, &settings)?;
+
+    // Clients are built from settings, the next request creates fresh ones
+    STATE.clients.clear();
+    if let Some(workers) = STATE.workers.lock().unwrap().as_mut() {
+        workers.resize(settings.upload_workers);
+    }
+    utils::logging::set_level(settings.log_level);
 
-    store.set(
-        "settings",
-        serde_json::to_value(&settings).map_err(|e| e.to_string())?,
-    );
-    store.save().map_err(|e| e.to_string())?;
-    
     Ok(())
 }
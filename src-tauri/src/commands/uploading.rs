@@ -1,76 +1,76 @@
-use walkdir::WalkDir;
-use reqwest::blocking::Client;
-use std::time::Duration;
-use crate::utils::{self, erabooru::UploadResult};
+use std::path::Path;
+use tracing::info;
+use crate::utils::{
+    self,
+    erabooru::Error,
+    queue::{Job, JobState},
+};
+use crate::STATE;
 
 #[tauri::command]
-pub fn scan_folder(folder: String) -> Result<(u64, u64, u64), String> {
-    let mut videos = 0u64;
-    let mut images = 0u64;
-    let mut size = 0u64;
-    for entry in WalkDir::new(&folder).into_iter().filter_map(|e| e.ok()) {
-        let path = entry.path();
-        if utils::files::is_media_file(path) {
-            let metadata = std::fs::metadata(path).map_err(|e| e.to_string())?;
-            size += metadata.len();
-            let guess = mime_guess::MimeGuess::from_path(path).first();
-            if let Some(m) = guess {
-                if m.type_() == mime_guess::mime::IMAGE {
-                    images += 1;
-                } else if m.type_() == mime_guess::mime::VIDEO {
-                    videos += 1;
-                }
-            }
-        }
-    }
-    Ok((videos, images, size))
+pub fn scan_folder(folder: String) -> Result<(u64, u64, u64), Error> {
+    utils::upload::scan_folder(Path::new(&folder))
 }
 
+/// Starts a background job queueing every media file under `folder` for
+/// upload to `target`. Returns the job id right away.
 #[tauri::command]
-pub fn upload_folder(app: tauri::AppHandle, folder: String) -> Result<(), String> {
-    let settings = utils::store::get_settings(&app)?;
-    if settings.server.is_empty() {
-        return Err("server not set".into());
+pub fn upload_folder(app: tauri::AppHandle, folder: String, target: String) -> Result<i64, Error> {
+    let settings = utils::store::get_settings(&app).map_err(Error::config)?;
+    let Some(server_target) = settings.target(&target) else {
+        return Err(Error::config(format!("unknown watch target: {}", target)));
+    };
+    if server_target.server.is_empty() {
+        return Err(Error::config("server not set"));
     }
-    let client = Client::new();
-    for entry in WalkDir::new(&folder).into_iter().filter_map(|e| e.ok()) {
-        let path = entry.path();
-        if utils::files::is_media_file(path) {
-            let data = match utils::files::retry_read_file(path, 3, Duration::from_millis(1000)) {
-                Ok(d) => d,
-                Err(e) => {
-                    println!("Failed to read file {}: {}", path.display(), e);
-                    continue;
-                }
-            };
-            let content_type = utils::files::get_file_mime_type(path)
-                .unwrap_or_else(|| "application/octet-stream".into());
-            match utils::erabooru::upload_media(&client, &settings.server, data, &content_type) {
-                Ok(UploadResult::Uploaded(id)) => {
-                    println!("✓ Uploaded: {}", path.display());
-                    utils::erabooru::apply_tags_and_date(
-                        &client,
-                        &settings.server,
-                        path,
-                        &id,
-                        &settings.auto_tags,
-                        settings.override_upload_date,
-                    );
-                }
-                Ok(UploadResult::Duplicate(id)) => {
-                    println!("⚠ Skipped (duplicate): {}", path.display());
-                    utils::erabooru::apply_tags_and_date(
-                        &client,
-                        &settings.server,
-                        path,
-                        &id,
-                        &settings.auto_tags,
-                        settings.override_upload_date,
-                    );
-                }
-                Err(e) => println!("✗ Failed to upload {}: {}", path.display(), e),
-            }
-        }
+    let folder = utils::upload::resolve_folder(Path::new(&folder))?;
+    let queue = STATE.queue().map_err(Error::io)?;
+    let hashes = STATE.hashes().map_err(Error::io)?;
+    let job = queue.create_job(&folder, &target).map_err(Error::io)?;
+    info!("Starting upload job {} for {}", job, folder);
+
+    std::thread::spawn(move || {
+        utils::upload::list_folder(&app, &queue, &hashes, job, &folder, &target);
+    });
+    Ok(job)
+}
+
+#[tauri::command]
+pub fn pause_upload(job: i64) -> Result<(), Error> {
+    set_job_state(job, JobState::Paused)
+}
+
+#[tauri::command]
+pub fn resume_upload(job: i64) -> Result<(), Error> {
+    set_job_state(job, JobState::Running)
+}
+
+/// Stops a job from queueing more files and drops its files that have not
+/// started uploading. Files already being uploaded finish.
+#[tauri::command]
+pub fn cancel_upload(job: i64) -> Result<(), Error> {
+    set_job_state(job, JobState::Cancelled)
+}
+
+#[tauri::command]
+pub fn upload_job(job: i64) -> Result<Job, Error> {
+    let queue = STATE.queue().map_err(Error::io)?;
+    queue
+        .job(job)
+        .map_err(Error::io)?
+        .ok_or_else(|| Error::config(format!("unknown upload job: {}", job)))
+}
+
+#[tauri::command]
+pub fn upload_jobs() -> Result<Vec<Job>, Error> {
+    STATE.queue().map_err(Error::io)?.jobs().map_err(Error::io)
+}
+
+fn set_job_state(job: i64, state: JobState) -> Result<(), Error> {
+    let queue = STATE.queue().map_err(Error::io)?;
+    if !queue.set_job_state(job, state).map_err(Error::io)? {
+        return Err(Error::config(format!("upload job {} is unknown or already cancelled", job)));
     }
+    info!("Upload job {} is now {}", job, state.as_str());
     Ok(())
 }
@@ -1,101 +1,146 @@
-use std::path::Path;
-use std::sync::mpsc::channel;
-use notify::{recommended_watcher, EventKind, RecursiveMode, Watcher};
-use reqwest::blocking::Client;
+use std::collections::HashMap;
+use std::sync::Arc;
+use serde::Serialize;
+use tracing::{info, warn};
 
-use crate::utils::erabooru::UploadResult;
+use crate::utils::erabooru::Error;
+use crate::utils::events::WatcherState;
+use crate::utils::watch_stats::Counters;
+use crate::utils::watcher;
 // Import from your other modules
-use crate::{STATE, WatcherHandle};
+use crate::STATE;
 use crate::utils;
 
+/// Starts a watcher for every enabled target that is not already running.
+/// A target that fails to start doesn't keep the others from starting, its
+/// error is returned under its id.
 #[tauri::command]
-pub fn start_watching(app: tauri::AppHandle) -> Result<(), String> {
-    let mut watcher_lock = STATE.watcher.lock().unwrap();
-    if watcher_lock.is_some() {
-        return Ok(());
-    }
-    
+pub fn start_watching(app: tauri::AppHandle) -> Result<HashMap<String, Error>, Error> {
     // Get settings from persistent store instead of memory
-    let settings = utils::store::get_settings(&app)?;
-    if settings.folder.is_empty() || settings.server.is_empty() {
-        return Err("folder or server not set".into());
+    let settings = utils::store::get_settings(&app).map_err(Error::config)?;
+    let targets: Vec<_> = settings.targets.into_iter().filter(|t| t.enabled).collect();
+    if targets.is_empty() {
+        return Err(Error::config("no watch targets configured"));
     }
-    
-    let (stop_tx, stop_rx) = channel::<()>();
-    let folder = settings.folder.clone();
-    let server = settings.server.clone();
-
-    //todo: lock settings changes while watching
-    println!("Starting watcher for folder: {}", folder);
-    let handle = std::thread::spawn(move || {
-        let client = Client::new();
-        let (tx, rx) = channel();
-        let mut watcher = recommended_watcher(move |res| {
-            tx.send(res).ok();
-        }).expect("watcher");
-        watcher
-            .watch(Path::new(&folder), RecursiveMode::NonRecursive)
-            .expect("watch");
-        loop {
-            if stop_rx.try_recv().is_ok() {
-                break;
-            }
-            if let Ok(Ok(event)) = rx.recv_timeout(std::time::Duration::from_millis(500)) {
-                if matches!(event.kind, EventKind::Create(_)) {
-                    for path in event.paths {
-                        if utils::files::is_media_file(&path) {
-                            let data = match utils::files::retry_read_file(&path, 3, std::time::Duration::from_millis(1000)) {
-                                Ok(d) => d,
-                                Err(e) => {
-                                    println!("Failed to read file {}: {}", path.display(), e);
-                                    continue;
-                                }
-                            };
 
-                            let content_type = utils::files::get_file_mime_type(&path)
-                                .unwrap_or_else(|| "application/octet-stream".into());
-
-                            match utils::erabooru::upload_media(&client, &settings.server, data, &content_type) {
-                                Ok(UploadResult::Uploaded(id)) => {
-                                    println!("✓ Uploaded: {}", path.display());
-                                    utils::erabooru::apply_tags_and_date(
-                                        &client,
-                                        &settings.server,
-                                        &path,
-                                        &id,
-                                        &settings.auto_tags,
-                                        settings.override_upload_date,
-                                    );
-                                }
-                                Ok(UploadResult::Duplicate(id)) => {
-                                    println!("⚠ Skipped (duplicate): {}", path.display());
-                                    utils::erabooru::apply_tags_and_date(
-                                        &client,
-                                        &settings.server,
-                                        &path,
-                                        &id,
-                                        &settings.auto_tags,
-                                        settings.override_upload_date,
-                                    );
-                                }
-                                Err(e) => println!("✗ Failed to upload {}: {}", path.display(), e),
-                            }
-                        }
-                    }
+    let ctx = watcher_context(&app)?;
+    let mut watchers = STATE.watchers.lock().unwrap();
+    if watchers.is_empty() {
+        STATE.watch_stats.reset();
+    }
+    let mut errors = HashMap::new();
+    for target in targets {
+        if !watchers.contains_key(&target.id) {
+            let id = target.id.clone();
+            match watcher::spawn(ctx.clone(), target) {
+                Ok(w) => {
+                    watchers.insert(id, w);
+                }
+                Err(e) => {
+                    warn!("Failed to start watching {}: {}", id, e);
+                    errors.insert(id, e);
                 }
             }
         }
-    });
-    *watcher_lock = Some(WatcherHandle { stop: stop_tx, handle });
-    Ok(())
+    }
+    Ok(errors)
 }
 
 #[tauri::command]
 pub fn stop_watching() {
-    println!("Stopping watcher");
-    if let Some(w) = STATE.watcher.lock().unwrap().take() {
-        let _ = w.stop.send(());
-        let _ = w.handle.join();
+    info!("Stopping all watchers");
+    let watchers: Vec<_> = STATE.watchers.lock().unwrap().drain().collect();
+    for (_, w) in watchers {
+        w.stop();
+    }
+}
+
+#[tauri::command]
+pub fn start_target(app: tauri::AppHandle, id: String) -> Result<(), Error> {
+    let settings = utils::store::get_settings(&app).map_err(Error::config)?;
+    let target = settings
+        .target(&id)
+        .cloned()
+        .ok_or_else(|| Error::config(format!("unknown watch target: {}", id)))?;
+
+    let ctx = watcher_context(&app)?;
+    let mut watchers = STATE.watchers.lock().unwrap();
+    if watchers.contains_key(&id) {
+        return Ok(());
+    }
+    if watchers.is_empty() {
+        STATE.watch_stats.reset();
+    }
+    watchers.insert(id, watcher::spawn(ctx, target)?);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn stop_target(id: String) {
+    info!("Stopping watcher for target: {}", id);
+    let watcher = STATE.watchers.lock().unwrap().remove(&id);
+    if let Some(w) = watcher {
+        w.stop();
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchState {
+    Running,
+    Stopped,
+    /// At least one folder is not watched right now and being retried
+    Errored,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchedFolder {
+    pub target: String,
+    pub folder: String,
+    #[serde(flatten)]
+    pub state: WatcherState,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchStatus {
+    pub state: WatchState,
+    pub folders: Vec<WatchedFolder>,
+    /// Counts since the first of the running watchers started
+    #[serde(flatten)]
+    pub counters: Counters,
+}
+
+#[tauri::command]
+pub fn watcher_status() -> WatchStatus {
+    let watchers = STATE.watchers.lock().unwrap();
+    let mut folders: Vec<_> = watchers
+        .iter()
+        .map(|(id, w)| WatchedFolder {
+            target: id.clone(),
+            folder: w.folder().to_string(),
+            state: w.state(),
+        })
+        .collect();
+    folders.sort_by(|a, b| a.folder.cmp(&b.folder));
+    let state = if folders.is_empty() {
+        WatchState::Stopped
+    } else if folders.iter().any(|f| matches!(f.state, WatcherState::Failed { .. })) {
+        WatchState::Errored
+    } else {
+        WatchState::Running
+    };
+    WatchStatus {
+        state,
+        folders,
+        counters: STATE.watch_stats.snapshot(),
     }
 }
 
+fn watcher_context(app: &tauri::AppHandle) -> Result<Arc<watcher::Context>, Error> {
+    Ok(Arc::new(watcher::Context {
+        events: Arc::new(app.clone()),
+        queue: STATE.queue().map_err(Error::io)?,
+        hashes: STATE.hashes().map_err(Error::io)?,
+        stats: STATE.watch_stats.clone(),
+    }))
+}
@@ -1,8 +1,23 @@
-use xxhash_rust::xxh3::xxh3_128;
-use reqwest::blocking::Client;
-use std::{path::Path, thread, time::Duration};
+use reqwest::blocking::{Body, Client, RequestBuilder, Response};
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::{debug, warn};
 
-use crate::utils::{files, tagging, store::AutoTagRule};
+use crate::utils::{
+    files,
+    retry::RetryPolicy,
+    store::{Auth, Settings, WatchTarget},
+    tagging::TagRules,
+};
 
 #[derive(Debug)]
 pub enum UploadResult {
@@ -10,201 +25,351 @@ pub enum UploadResult {
     Duplicate(String),
 }
 
-pub fn upload_media(
-    client: &Client,
-    server: &str,
-    data: Vec<u8>,
-    content_type: &str,
-) -> Result<UploadResult, String> {
-    let hash = xxh3_128(&data);
-    let filename = format!("{:032x}", hash);
-    let url = format!("{}/api/media/upload-url", server.trim_end_matches('/'));
-
-    let resp = client
-        .post(&url)
-        .json(&serde_json::json!({ "filename": filename }))
-        .send()
-        .map_err(|e| format!("Failed to get upload URL: {}", e))?;
-    
-    if !resp.status().is_success() {
-        return Err(format!("Upload URL request failed with status: {}", resp.status()));
-    }
-    
-    let response_text = resp.text().map_err(|e| format!("Failed to read response: {}", e))?;
-    
-    let upload_response: serde_json::Value = serde_json::from_str(&response_text)
-        .map_err(|e| format!("Failed to parse upload URL response: {}", e))?;
-    
-    let upload_url_path = upload_response["url"]
-        .as_str()
-        .ok_or("No 'url' field in upload response")?;
-
-    // Construct the full URL by combining server base URL with the relative path
-    let full_upload_url = if upload_url_path.starts_with("http") {
-        // Already a full URL
-        upload_url_path.to_string()
-    } else {
-        // Relative URL, combine with server base
-        format!("{}{}", server.trim_end_matches('/'), upload_url_path)
-    };
-
-    let put_resp = client
-        .put(&full_upload_url)  // Use the full URL here
-        .header(reqwest::header::CONTENT_TYPE, content_type)
-        .header(reqwest::header::IF_NONE_MATCH, "*")
-        .body(data)
-        .send()
-        .map_err(|e| format!("Failed to upload file: {}", e))?;
-
-    match put_resp.status().as_u16() {
-        200 | 201 | 204 => Ok(UploadResult::Uploaded(filename)),
-        412 => Ok(UploadResult::Duplicate(filename)),
-        status => {
-            let error_text = put_resp.text().unwrap_or_else(|_| "Unknown error".to_string());
-            Err(format!("Upload failed with status {}: {}", status, error_text))
+/// Everything that can go wrong while sending a file to an EraBooru server.
+/// Serialized with a `kind` tag so the frontend can tell the cases apart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Error {
+    /// The server could not be reached or the connection broke off
+    Network { message: String },
+    /// The server answered with an unexpected status
+    Http { status: u16, body: String },
+    /// The server rejected the configured credentials (401) or they lack
+    /// permission for the request (403)
+    Auth { status: u16, body: String },
+    /// A response body could not be read or parsed
+    Decode { message: String },
+    /// The server answered, but not the way the API promises
+    Protocol { message: String },
+    /// A local file could not be read
+    Io { message: String },
+    /// The upload could not be attempted because of missing or invalid settings
+    Config { message: String },
+    /// Recorded by an older version, only the text is known
+    Other { message: String },
+}
+
+impl Error {
+    /// Consumes an unsuccessful API response, keeping its body for diagnostics.
+    pub fn from_response(resp: Response) -> Self {
+        match Error::from_status(resp) {
+            Error::Http { status: status @ (401 | 403), body } => Error::Auth { status, body },
+            e => e,
         }
     }
-}
 
-pub fn add_tags(
-    client: &Client,
-    server: &str,
-    id: &str,
-    tags: &[&str],
-) -> Result<(), String> {
-    let url = format!("{}/api/media/{}/tags", server.trim_end_matches('/'), id);
-    
-    println!("Adding tags to {}: {:?}", id, tags);
-    
-    let resp = client
-        .post(&url)
-        .json(&serde_json::json!({ "tags": tags }))  
-        .send()
-        .map_err(|e| format!("Failed to add tags: {}", e))?;
-    
-    let status = resp.status();
-    if status.is_success() {
-        println!("Tags added successfully");
-        Ok(())
-    } else {
-        let error_text = resp.text().unwrap_or_else(|_| "Unknown error".to_string());
-        Err(format!("Failed to add tags, status {}: {}", status, error_text))
+    /// Like [`Error::from_response`], but without treating 401/403 as a
+    /// credentials problem. For responses of requests sent without them.
+    pub fn from_status(resp: Response) -> Self {
+        let status = resp.status().as_u16();
+        let body = resp.text().unwrap_or_default();
+        Error::Http { status, body }
     }
-}
 
-pub fn add_date(
-    client: &Client,
-    server: &str,
-    id: &str,
-    name: &str,
-    value: &str,
-) -> Result<(), String> {
-    let url = format!("{}/api/media/{}/dates", server.trim_end_matches('/'), id);
-
-    let resp = client
-        .post(&url)
-        .json(&serde_json::json!({ "dates": [{ "name": name, "value": value }] }))
-        .send()
-        .map_err(|e| format!("Failed to add date: {}", e))?;
-
-    let status = resp.status();
-    if status.is_success() {
-        Ok(())
-    } else {
-        let error_text = resp.text().unwrap_or_else(|_| "Unknown error".to_string());
-        Err(format!("Failed to add date, status {}: {}", status, error_text))
+    pub fn io(e: impl fmt::Display) -> Self {
+        Error::Io { message: e.to_string() }
+    }
+
+    pub fn config(message: impl Into<String>) -> Self {
+        Error::Config { message: message.into() }
     }
 }
 
-pub fn apply_tags_and_date(
-    client: &Client,
-    server: &str,
-    path: &Path,
-    id: &str,
-    auto_tags: &[AutoTagRule],
-    override_upload_date: bool,
-) {
-    // Wait for the media to be indexed before applying tags and dates
-    if let Err(e) = wait_for_media_indexing(
-        client, 
-        server, 
-        id, 
-        10, // max attempts
-        Duration::from_millis(500) // delay between attempts
-    ) {
-        println!("Failed to wait for media indexing for {}: {}", path.display(), e);
-        return;
-    }
-    
-    let tags = tagging::tags_for_path(path, auto_tags);
-    if !tags.is_empty() {
-        let tag_refs: Vec<&str> = tags.iter().map(|t| t.as_str()).collect();
-        if let Err(e) = add_tags(client, server, id, &tag_refs) {
-            println!("Failed to tag {}: {}", path.display(), e);
-        }
-    }
-
-    if override_upload_date {
-        if let Ok(date) = files::file_modified_utc(path) {
-            if let Err(e) = add_date(client, server, id, "upload", &date) {
-                println!("Failed to set date for {}: {}", path.display(), e);
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Network { message } => write!(f, "network error: {}", message),
+            Error::Http { status, body } if body.is_empty() => {
+                write!(f, "server returned status {}", status)
+            }
+            Error::Http { status, body } => write!(f, "server returned status {}: {}", status, body),
+            Error::Auth { status: 401, .. } => {
+                write!(f, "authentication failed (401): check the credentials for this server")
             }
+            Error::Auth { status, .. } => {
+                write!(f, "access denied ({}): the credentials lack permission for this request", status)
+            }
+            Error::Decode { message } => write!(f, "invalid response: {}", message),
+            Error::Protocol { message } => write!(f, "protocol error: {}", message),
+            Error::Io { message } => write!(f, "file error: {}", message),
+            Error::Config { message } => write!(f, "configuration error: {}", message),
+            Error::Other { message } => write!(f, "{}", message),
         }
     }
 }
 
-pub fn check_media_exists(
-    client: &Client,
-    server: &str,
-    id: &str,
-) -> Result<bool, String> {
-    let url = format!("{}/api/media/{}", server.trim_end_matches('/'), id);
-    
-    let resp = client
-        .get(&url)
-        .send()
-        .map_err(|e| format!("Failed to check media existence: {}", e))?;
-    
-    match resp.status().as_u16() {
-        200 => Ok(true),
-        404 => Ok(false),
-        status => Err(format!("Unexpected status when checking media: {}", status)),
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() {
+            Error::Decode { message: e.to_string() }
+        } else if let Some(status) = e.status() {
+            Error::Http { status: status.as_u16(), body: String::new() }
+        } else {
+            Error::Network { message: e.to_string() }
+        }
     }
 }
 
-pub fn wait_for_media_indexing(
-    client: &Client,
-    server: &str,
-    id: &str,
-    max_attempts: u32,
-    delay: Duration,
-) -> Result<(), String> {
-    println!("Waiting for media {} to be indexed...", id);
-    
-    for attempt in 1..=max_attempts {
-        match check_media_exists(client, server, id) {
-            Ok(true) => {
-                println!("Media {} is now available (attempt {})", id, attempt);
-                return Ok(());
-            }
-            Ok(false) => {
-                if attempt < max_attempts {
-                    println!("Media {} not yet available, waiting... (attempt {}/{})", id, attempt, max_attempts);
-                    thread::sleep(delay);
-                } else {
-                    return Err(format!("Media {} was not indexed after {} attempts", id, max_attempts));
-                }
+#[derive(Deserialize)]
+struct UploadUrlResponse {
+    url: Option<String>,
+}
+
+// Upload progress is reported about once per this many bytes
+const PROGRESS_STEP: u64 = 1024 * 1024;
+
+/// Counts the bytes of a request body as reqwest reads them.
+struct ProgressReader<R> {
+    inner: R,
+    sent: u64,
+    reported: u64,
+    total: u64,
+    on_sent: Arc<dyn Fn(u64, u64) + Send + Sync>,
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.sent += n as u64;
+        if self.sent - self.reported >= PROGRESS_STEP || (n > 0 && self.sent >= self.total) {
+            self.reported = self.sent;
+            (self.on_sent)(self.sent, self.total);
+        }
+        Ok(n)
+    }
+}
+
+/// A configured connection to one EraBooru server. Cloning is cheap and
+/// clones share the underlying HTTP connection pool.
+#[derive(Clone)]
+pub struct ErabooruClient {
+    base_url: String,
+    http: Client,
+    retry: RetryPolicy,
+    timeout: Duration,
+    auth: Auth,
+}
+
+impl ErabooruClient {
+    pub fn new(target: &WatchTarget, settings: &Settings) -> Result<Self, Error> {
+        let server = target.server.trim().trim_end_matches('/');
+        if server.is_empty() {
+            return Err(Error::config("server not set"));
+        }
+        // Happens when the saved secrets could no longer be decrypted
+        if let Auth::Bearer { token: secret } | Auth::Header { value: secret, .. } = &target.auth {
+            if secret.is_empty() {
+                return Err(Error::config("credentials are missing, enter them again in Settings"));
             }
-            Err(e) => {
-                println!("Error checking media existence: {}", e);
-                if attempt < max_attempts {
-                    thread::sleep(delay);
-                } else {
-                    return Err(format!("Failed to verify media indexing: {}", e));
-                }
+        }
+        let timeout = Duration::from_secs(settings.request_timeout_secs);
+        let http = Client::builder()
+            .connect_timeout(Duration::from_secs(settings.connect_timeout_secs))
+            .timeout(timeout)
+            .build()
+            .map_err(|e| Error::config(format!("failed to create HTTP client: {}", e)))?;
+        Ok(ErabooruClient {
+            base_url: server.to_string(),
+            http,
+            retry: settings.retry.clone(),
+            timeout,
+            auth: target.auth.clone(),
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// Starts an API request carrying the configured credentials.
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        let req = self.http.request(method, url);
+        match &self.auth {
+            Auth::None => req,
+            Auth::Bearer { token } => req.bearer_auth(token),
+            Auth::Basic { username, password } => req.basic_auth(username, Some(password)),
+            Auth::Header { name, value } => req.header(name.as_str(), value.as_str()),
+        }
+    }
+
+    /// Uploads a file under its content hash, see [`files::hash_file`].
+    /// `on_sent` is called with the bytes sent so far and the file size
+    /// while the file is being sent.
+    pub fn upload_media(
+        &self,
+        file: File,
+        hash: &str,
+        content_type: &str,
+        on_sent: impl Fn(u64, u64) + Send + Sync + 'static,
+    ) -> Result<UploadResult, Error> {
+        let filename = hash.to_string();
+
+        // The media id is the content hash, so a duplicate can be recognized
+        // before sending any bytes. If the check fails, the 412 on the PUT
+        // below still catches it.
+        match self.check_media_exists(&filename) {
+            Ok(true) => return Ok(UploadResult::Duplicate(filename)),
+            Ok(false) => {}
+            Err(e @ Error::Auth { .. }) => return Err(e),
+            Err(e) => warn!("Could not check whether {} exists, uploading anyway: {}", filename, e),
+        }
+
+        let size = file.metadata().map_err(Error::io)?.len();
+        let url = self.url("/api/media/upload-url");
+
+        let resp = self.retry.send(|| {
+            Ok(self.request(Method::POST, &url).json(&serde_json::json!({ "filename": filename })))
+        })?;
+        if !resp.status().is_success() {
+            return Err(Error::from_response(resp));
+        }
+
+        let response_text = resp.text()?;
+        let upload_response: UploadUrlResponse = serde_json::from_str(&response_text)
+            .map_err(|e| Error::Decode { message: format!("upload URL response: {}", e) })?;
+        let upload_url_path = upload_response.url.ok_or_else(|| Error::Protocol {
+            message: "no 'url' field in upload URL response".into(),
+        })?;
+
+        // Construct the full URL by combining server base URL with the relative path
+        let full_upload_url = if upload_url_path.starts_with("http") {
+            // Already a full URL
+            upload_url_path
+        } else {
+            // Relative URL, combine with server base
+            self.url(&upload_url_path)
+        };
+
+        // The regular timeout is far too short for large files,
+        // allow for uplinks down to about 1 MiB/s
+        let upload_timeout = self.timeout + Duration::from_secs(size / (1024 * 1024));
+        let on_sent: Arc<dyn Fn(u64, u64) + Send + Sync> = Arc::new(on_sent);
+        let put_resp = self.retry.send(|| {
+            // Every attempt streams the file again from the start
+            let mut body = file.try_clone().map_err(Error::io)?;
+            body.seek(SeekFrom::Start(0)).map_err(Error::io)?;
+            Ok(self
+                .http
+                .put(&full_upload_url)  // Use the full URL here
+                .header(reqwest::header::CONTENT_TYPE, content_type)
+                .header(reqwest::header::IF_NONE_MATCH, "*")
+                .timeout(upload_timeout)
+                // Streamed from disk, the file is never held in memory as a whole
+                .body(Body::sized(
+                    ProgressReader {
+                        inner: body,
+                        sent: 0,
+                        reported: 0,
+                        total: size,
+                        on_sent: on_sent.clone(),
+                    },
+                    size,
+                )))
+        })?;
+
+        match put_resp.status().as_u16() {
+            200 | 201 | 204 => Ok(UploadResult::Uploaded(filename)),
+            412 => Ok(UploadResult::Duplicate(filename)),
+            _ => Err(Error::from_status(put_resp)),
+        }
+    }
+
+    pub fn add_tags(&self, id: &str, tags: &[&str]) -> Result<(), Error> {
+        let url = self.url(&format!("/api/media/{}/tags", id));
+
+        debug!("Adding tags to {}: {:?}", id, tags);
+
+        let resp = self
+            .retry
+            .send(|| Ok(self.request(Method::POST, &url).json(&serde_json::json!({ "tags": tags }))))?;
+        if resp.status().is_success() {
+            debug!("Tags added to {}", id);
+            Ok(())
+        } else {
+            Err(Error::from_response(resp))
+        }
+    }
+
+    pub fn add_date(&self, id: &str, name: &str, value: &str) -> Result<(), Error> {
+        let url = self.url(&format!("/api/media/{}/dates", id));
+
+        let body = serde_json::json!({ "dates": [{ "name": name, "value": value }] });
+        let resp = self.retry.send(|| Ok(self.request(Method::POST, &url).json(&body)))?;
+        if resp.status().is_success() {
+            Ok(())
+        } else {
+            Err(Error::from_response(resp))
+        }
+    }
+
+    /// Applies `tags` and, if enabled, the upload date taken from `path`.
+    /// Both are attempted even if one fails, the first error is returned.
+    pub fn apply_tags_and_date(
+        &self,
+        path: &Path,
+        tags: &[String],
+        id: &str,
+        override_upload_date: bool,
+    ) -> Result<(), Error> {
+        let mut result = Ok(());
+        if !tags.is_empty() {
+            let tag_refs: Vec<&str> = tags.iter().map(|t| t.as_str()).collect();
+            result = self.add_tags(id, &tag_refs);
+        }
+
+        if override_upload_date {
+            if let Ok(date) = files::file_modified_utc(path) {
+                let dated = self.add_date(id, "upload", &date);
+                result = result.and(dated);
             }
         }
+        result
+    }
+
+    pub fn check_media_exists(&self, id: &str) -> Result<bool, Error> {
+        let url = self.url(&format!("/api/media/{}", id));
+
+        let resp = self.retry.send(|| Ok(self.request(Method::GET, &url)))?;
+        match resp.status().as_u16() {
+            200 => Ok(true),
+            404 => Ok(false),
+            _ => Err(Error::from_response(resp)),
+        }
+    }
+}
+
+/// Keeps one client per watch target so connections are reused across
+/// uploads, along with the target's compiled auto-tag rules. Must be cleared
+/// whenever settings change.
+#[derive(Default)]
+pub struct ClientCache {
+    clients: Mutex<HashMap<String, ErabooruClient>>,
+    tag_rules: Mutex<HashMap<String, Arc<TagRules>>>,
+}
+
+impl ClientCache {
+    pub fn get(&self, settings: &Settings, target: &WatchTarget) -> Result<ErabooruClient, Error> {
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(&target.id) {
+            return Ok(client.clone());
+        }
+        let client = ErabooruClient::new(target, settings)?;
+        clients.insert(target.id.clone(), client.clone());
+        Ok(client)
+    }
+
+    pub fn tag_rules(&self, target: &WatchTarget) -> Arc<TagRules> {
+        let mut rules = self.tag_rules.lock().unwrap();
+        rules
+            .entry(target.id.clone())
+            .or_insert_with(|| Arc::new(TagRules::new(&target.auto_tags)))
+            .clone()
+    }
+
+    pub fn clear(&self) {
+        self.clients.lock().unwrap().clear();
+        self.tag_rules.lock().unwrap().clear();
     }
-    
-    Ok(())
 }
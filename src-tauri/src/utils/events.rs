@@ -0,0 +1,87 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::utils::{erabooru::Error, queue::QueueSource};
+
+/// Name of the event the frontend listens to for upload progress
+pub const PROGRESS_EVENT: &str = "upload-progress";
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Status {
+    Detected,
+    Hashing,
+    Uploading { sent: u64, total: u64 },
+    Uploaded { media_id: String },
+    Duplicate { media_id: String },
+    Tagged { media_id: String },
+    Failed { error: Error },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Progress {
+    pub path: PathBuf,
+    pub target: String,
+    pub source: QueueSource,
+    #[serde(flatten)]
+    pub status: Status,
+}
+
+/// Where progress and watcher events go: the window in the app, nowhere in
+/// the command line tool, which only logs.
+pub trait EventSink: Send + Sync {
+    fn progress(&self, event: Progress);
+    fn watcher(&self, event: WatcherStatus);
+}
+
+#[cfg(feature = "gui")]
+impl EventSink for tauri::AppHandle {
+    fn progress(&self, event: Progress) {
+        use tauri::Emitter;
+        if let Err(e) = self.emit(PROGRESS_EVENT, event) {
+            tracing::warn!("Failed to emit progress event: {}", e);
+        }
+    }
+
+    fn watcher(&self, event: WatcherStatus) {
+        use tauri::Emitter;
+        if let Err(e) = self.emit(WATCHER_EVENT, event) {
+            tracing::warn!("Failed to emit watcher event: {}", e);
+        }
+    }
+}
+
+pub fn emit(sink: &dyn EventSink, path: &Path, target: &str, source: QueueSource, status: Status) {
+    sink.progress(Progress {
+        path: path.to_path_buf(),
+        target: target.to_string(),
+        source,
+        status,
+    });
+}
+
+/// Name of the event the frontend listens to for watcher health
+pub const WATCHER_EVENT: &str = "watcher-status";
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum WatcherState {
+    Running,
+    /// The watch broke, e.g. the folder was deleted or unmounted. It is
+    /// re-armed after `retry_in_ms`.
+    Failed { error: String, retry_in_ms: u64 },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WatcherStatus {
+    pub target: String,
+    #[serde(flatten)]
+    pub state: WatcherState,
+}
+
+pub fn emit_watcher(sink: &dyn EventSink, target: &str, state: WatcherState) {
+    sink.watcher(WatcherStatus {
+        target: target.to_string(),
+        state,
+    });
+}
@@ -1,6 +1,12 @@
-use std::path::Path;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use xxhash_rust::xxh3::Xxh3;
 use mime_guess::MimeGuess;
-use chrono::prelude::*;
+use tracing::warn;
+use walkdir::WalkDir;
+
+const HASH_CHUNK_SIZE: usize = 256 * 1024;
 
 pub fn is_media_file(path: &Path) -> bool {
     if !path.is_file() {
@@ -14,6 +20,16 @@ pub fn is_media_file(path: &Path) -> bool {
     }
 }
 
+pub fn media_files(root: &Path, recursive: bool) -> impl Iterator<Item = PathBuf> {
+    let max_depth = if recursive { usize::MAX } else { 1 };
+    WalkDir::new(root)
+        .max_depth(max_depth)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .map(|e| e.into_path())
+        .filter(|p| is_media_file(p))
+}
+
 pub fn get_file_mime_type(path: &Path) -> Option<String> {
     if !path.is_file() {
         return None;
@@ -22,18 +38,19 @@ pub fn get_file_mime_type(path: &Path) -> Option<String> {
     guess.map(|m| m.essence_str().to_string())
 }
 
-pub fn retry_read_file(
+pub fn retry_open_file(
     path: &Path,
     max_retries: u32,
     delay: std::time::Duration,
-) -> Result<Vec<u8>, String> {
+) -> Result<File, String> {
     for attempt in 0..max_retries {
-        match std::fs::read(path) {
-            Ok(data) => return Ok(data),
+        match File::open(path) {
+            Ok(file) => return Ok(file),
             Err(e) if e.raw_os_error() == Some(32) => {
                 // OS error 32 = file is locked
-                println!(
-                    "File locked, attempt {}/{}, waiting...",
+                warn!(
+                    "{} is locked, attempt {}/{}, waiting...",
+                    path.display(),
                     attempt + 1,
                     max_retries
                 );
@@ -46,6 +63,24 @@ pub fn retry_read_file(
     Err("File remained locked after all retry attempts".to_string())
 }
 
+/// Computes the xxh3-128 content id of a file in fixed-size chunks so memory
+/// use does not depend on the file size. The file is rewound afterwards.
+pub fn hash_file<R: Read + Seek>(file: &mut R) -> Result<String, String> {
+    let mut hasher = Xxh3::new();
+    let mut buf = vec![0u8; HASH_CHUNK_SIZE];
+    loop {
+        let n = match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.to_string()),
+        };
+        hasher.update(&buf[..n]);
+    }
+    file.seek(SeekFrom::Start(0)).map_err(|e| e.to_string())?;
+    Ok(format!("{:032x}", hasher.digest128()))
+}
+
 pub fn file_modified_utc(path: &Path) -> Result<String, String> {
     let metadata = std::fs::metadata(path).map_err(|e| e.to_string())?;
     let modified = metadata.modified().map_err(|e| e.to_string())?;
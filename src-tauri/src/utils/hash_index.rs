@@ -0,0 +1,245 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fs::Metadata;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use crate::utils::queue::folder_prefix;
+
+/// Size and modification time of a file, used to tell whether it changed
+/// since it was last hashed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub size: u64,
+    pub mtime_ms: i64,
+}
+
+impl Fingerprint {
+    pub fn of(metadata: &Metadata) -> Self {
+        let mtime_ms = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or(0);
+        Fingerprint {
+            size: metadata.len(),
+            mtime_ms,
+        }
+    }
+
+    pub fn read(path: &Path) -> std::io::Result<Self> {
+        std::fs::metadata(path).map(|m| Fingerprint::of(&m))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Uploaded,
+    Duplicate,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Uploaded => "uploaded",
+            Outcome::Duplicate => "duplicate",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "uploaded" => Some(Outcome::Uploaded),
+            "duplicate" => Some(Outcome::Duplicate),
+            _ => None,
+        }
+    }
+}
+
+pub struct Entry {
+    pub hash: String,
+    /// How the last upload to the target went, `None` if it never finished
+    pub outcome: Option<Outcome>,
+}
+
+/// Remembers the content hash of every file seen per watch target, keyed by
+/// path, so unchanged files are neither hashed nor uploaded again. A file
+/// counts as unchanged while its size and modification time stay the same.
+pub struct HashIndex {
+    conn: Mutex<Connection>,
+}
+
+impl HashIndex {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let conn = Connection::open(path).map_err(|e| e.to_string())?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS files (
+                 path TEXT NOT NULL,
+                 target TEXT NOT NULL,
+                 size INTEGER NOT NULL,
+                 mtime_ms INTEGER NOT NULL,
+                 hash TEXT NOT NULL,
+                 outcome TEXT,
+                 updated_at TEXT NOT NULL,
+                 PRIMARY KEY (path, target)
+             );",
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(HashIndex {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Returns what is known about the file, unless it changed since.
+    pub fn lookup(&self, path: &Path, target: &str, fingerprint: Fingerprint) -> Result<Option<Entry>, String> {
+        let conn = self.conn.lock().unwrap();
+        let row: Option<(String, Option<String>)> = conn
+            .query_row(
+                "SELECT hash, outcome FROM files
+                 WHERE path = ?1 AND target = ?2 AND size = ?3 AND mtime_ms = ?4",
+                params![path.to_string_lossy(), target, fingerprint.size, fingerprint.mtime_ms],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        Ok(row.map(|(hash, outcome)| Entry {
+            hash,
+            outcome: outcome.and_then(|o| Outcome::parse(&o)),
+        }))
+    }
+
+    /// Whether the file was uploaded to the target before and has not
+    /// changed since. Errors count as not uploaded.
+    pub fn is_uploaded(&self, path: &Path, target: &str) -> bool {
+        let Ok(fingerprint) = Fingerprint::read(path) else {
+            return false;
+        };
+        matches!(self.lookup(path, target, fingerprint), Ok(Some(entry)) if entry.outcome.is_some())
+    }
+
+    /// Moves what is known about a file, or every file in a folder, to the
+    /// name it was renamed to. Size and modification time survive a rename,
+    /// so files uploaded under the old name still count as uploaded.
+    pub fn rename(&self, from: &Path, to: &Path, target: &str) -> Result<(), String> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE OR REPLACE files SET path = ?1 || substr(path, length(?2) + 1)
+             WHERE target = ?3 AND (path = ?2 OR substr(path, 1, length(?4)) = ?4)",
+            params![to.to_string_lossy(), from.to_string_lossy(), target, folder_prefix(from)],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Stores the hash of a file, along with the outcome of its upload once known.
+    pub fn record(
+        &self,
+        path: &Path,
+        target: &str,
+        fingerprint: Fingerprint,
+        hash: &str,
+        outcome: Option<Outcome>,
+    ) -> Result<(), String> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO files (path, target, size, mtime_ms, hash, outcome, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT (path, target) DO UPDATE SET
+                 size = excluded.size, mtime_ms = excluded.mtime_ms, hash = excluded.hash,
+                 outcome = excluded.outcome, updated_at = excluded.updated_at",
+            params![
+                path.to_string_lossy(),
+                target,
+                fingerprint.size,
+                fingerprint.mtime_ms,
+                hash,
+                outcome.map(|o| o.as_str()),
+                chrono::Utc::now().to_rfc3339()
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRINT: Fingerprint = Fingerprint { size: 10, mtime_ms: 1000 };
+
+    fn open() -> HashIndex {
+        HashIndex::open(Path::new(":memory:")).unwrap()
+    }
+
+    fn hash(index: &HashIndex, path: &str, target: &str) -> Option<String> {
+        index.lookup(Path::new(path), target, PRINT).unwrap().map(|e| e.hash)
+    }
+
+    #[test]
+    fn lookup_returns_recorded_entry() {
+        let index = open();
+        index.record(Path::new("/a.png"), "t", PRINT, "h1", None).unwrap();
+        let entry = index.lookup(Path::new("/a.png"), "t", PRINT).unwrap().unwrap();
+        assert_eq!((entry.hash.as_str(), entry.outcome), ("h1", None));
+
+        index.record(Path::new("/a.png"), "t", PRINT, "h1", Some(Outcome::Duplicate)).unwrap();
+        let entry = index.lookup(Path::new("/a.png"), "t", PRINT).unwrap().unwrap();
+        assert_eq!(entry.outcome, Some(Outcome::Duplicate));
+        assert_eq!(hash(&index, "/a.png", "other"), None);
+    }
+
+    #[test]
+    fn lookup_misses_changed_files() {
+        let index = open();
+        index.record(Path::new("/a.png"), "t", PRINT, "h1", Some(Outcome::Uploaded)).unwrap();
+        let grown = Fingerprint { size: 11, ..PRINT };
+        let touched = Fingerprint { mtime_ms: 2000, ..PRINT };
+        assert!(index.lookup(Path::new("/a.png"), "t", grown).unwrap().is_none());
+        assert!(index.lookup(Path::new("/a.png"), "t", touched).unwrap().is_none());
+    }
+
+    #[test]
+    fn is_uploaded_per_target() {
+        let dir = std::env::temp_dir().join(format!("hash-index-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.png");
+        std::fs::write(&path, b"data").unwrap();
+
+        let index = open();
+        let fingerprint = Fingerprint::read(&path).unwrap();
+        index.record(&path, "a", fingerprint, "h1", Some(Outcome::Uploaded)).unwrap();
+        index.record(&path, "b", fingerprint, "h1", None).unwrap();
+        assert!(index.is_uploaded(&path, "a"));
+        assert!(!index.is_uploaded(&path, "b"));
+        assert!(!index.is_uploaded(&path, "c"));
+
+        std::fs::write(&path, b"changed").unwrap();
+        assert!(!index.is_uploaded(&path, "a"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rename_moves_files_and_folders() {
+        let index = open();
+        let sep = std::path::MAIN_SEPARATOR;
+        let (nested, sibling) = (format!("/w/sub{}b.png", sep), format!("/w/sub2{}c.png", sep));
+        for (path, hash) in [("/w/a.png", "a"), ("/w/z.png", "z"), (&nested, "b"), (&sibling, "c")] {
+            index.record(Path::new(path), "t", PRINT, hash, Some(Outcome::Uploaded)).unwrap();
+        }
+
+        // Whatever was known about the new name is replaced
+        index.rename(Path::new("/w/a.png"), Path::new("/w/z.png"), "t").unwrap();
+        index.rename(Path::new("/w/sub"), Path::new("/w/new"), "t").unwrap();
+
+        assert_eq!(hash(&index, "/w/a.png", "t"), None);
+        assert_eq!(hash(&index, "/w/z.png", "t").as_deref(), Some("a"));
+        assert_eq!(hash(&index, &nested, "t"), None);
+        assert_eq!(hash(&index, &format!("/w/new{}b.png", sep), "t").as_deref(), Some("b"));
+        assert_eq!(hash(&index, &sibling, "t").as_deref(), Some("c"));
+    }
+}
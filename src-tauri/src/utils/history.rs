@@ -0,0 +1,298 @@
+use rusqlite::{params, params_from_iter, Connection};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::utils::{
+    erabooru::Error,
+    queue::{self, QueueItem, QueueSource},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryStatus {
+    Uploaded,
+    Duplicate,
+    Tagged,
+    Failed,
+}
+
+impl HistoryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HistoryStatus::Uploaded => "uploaded",
+            HistoryStatus::Duplicate => "duplicate",
+            HistoryStatus::Tagged => "tagged",
+            HistoryStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "uploaded" => Some(HistoryStatus::Uploaded),
+            "duplicate" => Some(HistoryStatus::Duplicate),
+            "tagged" => Some(HistoryStatus::Tagged),
+            "failed" => Some(HistoryStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEntry {
+    pub id: i64,
+    pub time: String,
+    pub path: PathBuf,
+    pub target: String,
+    pub source: QueueSource,
+    pub status: HistoryStatus,
+    /// Content hash the media is stored under on the server
+    pub media_id: Option<String>,
+    pub tags: Vec<String>,
+    pub error: Option<Error>,
+}
+
+/// Filters for [`History::query`]. Times are RFC 3339 timestamps or plain
+/// dates, `from` is inclusive and `to` exclusive.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct HistoryQuery {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub status: Option<HistoryStatus>,
+    /// Only files inside this folder, including sub-folders
+    pub folder: Option<String>,
+    pub media_id: Option<String>,
+    pub limit: Option<u32>,
+}
+
+/// Append-only log of every upload and tagging outcome, kept in its own
+/// SQLite database in the app data dir.
+pub struct History {
+    conn: Mutex<Connection>,
+}
+
+impl History {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let conn = Connection::open(path).map_err(|e| e.to_string())?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS history (
+                 id INTEGER PRIMARY KEY AUTOINCREMENT,
+                 time TEXT NOT NULL,
+                 path TEXT NOT NULL,
+                 target TEXT NOT NULL,
+                 source TEXT NOT NULL,
+                 status TEXT NOT NULL,
+                 media_id TEXT,
+                 tags TEXT NOT NULL DEFAULT '[]',
+                 error TEXT
+             );
+             CREATE INDEX IF NOT EXISTS history_time ON history(time);
+             CREATE INDEX IF NOT EXISTS history_media_id ON history(media_id);",
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(History {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn record(
+        &self,
+        item: &QueueItem,
+        status: HistoryStatus,
+        media_id: Option<&str>,
+        tags: &[String],
+        error: Option<&Error>,
+    ) -> Result<(), String> {
+        let tags = serde_json::to_string(tags).map_err(|e| e.to_string())?;
+        let error = error
+            .map(serde_json::to_string)
+            .transpose()
+            .map_err(|e| e.to_string())?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO history (time, path, target, source, status, media_id, tags, error)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                chrono::Utc::now().to_rfc3339(),
+                item.path.to_string_lossy(),
+                item.target,
+                item.source.as_str(),
+                status.as_str(),
+                media_id,
+                tags,
+                error
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Matching entries, newest first.
+    pub fn query(&self, query: &HistoryQuery) -> Result<Vec<HistoryEntry>, String> {
+        let mut conditions = Vec::new();
+        let mut values = Vec::new();
+        let mut filter = |condition: &str, value: Option<&str>| {
+            if let Some(value) = value {
+                values.push(value.to_string());
+                conditions.push(condition.replace('?', &format!("?{}", values.len())));
+            }
+        };
+        filter("time >= ?", query.from.as_deref());
+        filter("time < ?", query.to.as_deref());
+        filter("status = ?", query.status.as_ref().map(|s| s.as_str()));
+        // Compared with a separator at the end so `/photos` doesn't match `/photos2`
+        let folder = query.folder.as_deref().map(|f| {
+            format!("{}{}", f.trim_end_matches(['/', '\\']), std::path::MAIN_SEPARATOR)
+        });
+        filter("substr(path, 1, length(?)) = ?", folder.as_deref());
+        filter("media_id = ?", query.media_id.as_deref());
+
+        let mut sql = "SELECT id, time, path, target, source, status, media_id, tags, error FROM history"
+            .to_string();
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+        sql.push_str(" ORDER BY id DESC");
+        if let Some(limit) = query.limit {
+            sql.push_str(&format!(" LIMIT {}", limit));
+        }
+
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&sql).map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params_from_iter(values), row_to_entry)
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+    }
+}
+
+fn row_to_entry(row: &rusqlite::Row) -> rusqlite::Result<HistoryEntry> {
+    let path: String = row.get(2)?;
+    let source: String = row.get(4)?;
+    let status: String = row.get(5)?;
+    let tags: String = row.get(7)?;
+    let error: Option<String> = row.get(8)?;
+    Ok(HistoryEntry {
+        id: row.get(0)?,
+        time: row.get(1)?,
+        path: PathBuf::from(path),
+        target: row.get(3)?,
+        source: QueueSource::parse(&source).unwrap_or(QueueSource::Watch),
+        status: HistoryStatus::parse(&status).unwrap_or(HistoryStatus::Failed),
+        media_id: row.get(6)?,
+        tags: serde_json::from_str(&tags).unwrap_or_default(),
+        error: error.map(|e| queue::parse_error(&e)),
+    })
+}
+
+/// Writes entries as CSV, with tags joined by commas and errors as text.
+pub fn write_csv(entries: &[HistoryEntry], out: impl std::io::Write) -> Result<(), String> {
+    let mut writer = csv::Writer::from_writer(out);
+    writer
+        .write_record(["id", "time", "path", "target", "source", "status", "media_id", "tags", "error"])
+        .map_err(|e| e.to_string())?;
+    for e in entries {
+        writer
+            .write_record([
+                e.id.to_string(),
+                e.time.clone(),
+                e.path.to_string_lossy().into_owned(),
+                e.target.clone(),
+                e.source.as_str().to_string(),
+                e.status.as_str().to_string(),
+                e.media_id.clone().unwrap_or_default(),
+                e.tags.join(","),
+                e.error.as_ref().map(|e| e.to_string()).unwrap_or_default(),
+            ])
+            .map_err(|e| e.to_string())?;
+    }
+    writer.flush().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::queue::QueueState;
+
+    fn open() -> History {
+        History::open(Path::new(":memory:")).unwrap()
+    }
+
+    fn item(path: &str) -> QueueItem {
+        QueueItem {
+            id: 1,
+            path: PathBuf::from(path),
+            source: QueueSource::Watch,
+            target: "t".into(),
+            state: QueueState::Done,
+            job: None,
+            media_id: None,
+            duplicate: false,
+            error: None,
+            attempts: 1,
+            index_checks: 0,
+            created_at: String::new(),
+            updated_at: String::new(),
+        }
+    }
+
+    fn paths(history: &History, query: HistoryQuery) -> Vec<PathBuf> {
+        history.query(&query).unwrap().into_iter().map(|e| e.path).collect()
+    }
+
+    #[test]
+    fn query_filters_newest_first() {
+        let history = open();
+        let tags = ["cat".to_string()];
+        history.record(&item("/a.png"), HistoryStatus::Uploaded, Some("m1"), &tags, None).unwrap();
+        history.record(&item("/b.png"), HistoryStatus::Failed, None, &[], Some(&Error::config("bad"))).unwrap();
+        history.record(&item("/c.png"), HistoryStatus::Tagged, Some("m1"), &tags, None).unwrap();
+
+        let all = history.query(&HistoryQuery::default()).unwrap();
+        assert_eq!(all.iter().map(|e| e.path.to_str().unwrap()).collect::<Vec<_>>(), ["/c.png", "/b.png", "/a.png"]);
+        assert_eq!(all[0].tags, tags);
+        assert_eq!(all[1].error.as_ref().map(|e| e.to_string()), Some(Error::config("bad").to_string()));
+
+        let failed = HistoryQuery { status: Some(HistoryStatus::Failed), ..Default::default() };
+        assert_eq!(paths(&history, failed), [PathBuf::from("/b.png")]);
+        let media = HistoryQuery { media_id: Some("m1".into()), limit: Some(1), ..Default::default() };
+        assert_eq!(paths(&history, media), [PathBuf::from("/c.png")]);
+    }
+
+    #[test]
+    fn csv_joins_tags() {
+        let history = open();
+        let tags = ["cat".to_string(), "pet".to_string()];
+        history.record(&item("/a.png"), HistoryStatus::Uploaded, Some("m1"), &tags, None).unwrap();
+
+        let mut out = Vec::new();
+        write_csv(&history.query(&HistoryQuery::default()).unwrap(), &mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("id,time,path,target,source,status,media_id,tags,error"));
+        assert!(lines.next().unwrap().ends_with(",/a.png,t,watch,uploaded,m1,\"cat,pet\","));
+    }
+
+    #[test]
+    fn folder_filter_matches_whole_folder_names() {
+        let history = open();
+        let sep = std::path::MAIN_SEPARATOR;
+        for path in [format!("/a{0}b{0}x.png", sep), format!("/a{0}bc{0}y.png", sep), format!("/a{0}b{0}c{0}z.png", sep)] {
+            history.record(&item(&path), HistoryStatus::Uploaded, None, &[], None).unwrap();
+        }
+
+        for folder in [format!("/a{}b", sep), format!("/a{0}b{0}", sep)] {
+            let query = HistoryQuery { folder: Some(folder), ..Default::default() };
+            assert_eq!(
+                paths(&history, query),
+                [PathBuf::from(format!("/a{0}b{0}c{0}z.png", sep)), PathBuf::from(format!("/a{0}b{0}x.png", sep))]
+            );
+        }
+    }
+}
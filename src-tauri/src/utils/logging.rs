@@ -0,0 +1,107 @@
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{filter::LevelFilter, fmt, layer::SubscriberExt, reload, util::SubscriberInitExt, Registry};
+
+const FILE_PREFIX: &str = "erabooru";
+const FILE_SUFFIX: &str = "log";
+// Files rotate daily, this keeps about a week
+const MAX_FILES: usize = 7;
+// Tailing only reads this far back from the end of the file
+const TAIL_BYTES: u64 = 256 * 1024;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    fn filter(self) -> LevelFilter {
+        match self {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+static LEVEL: OnceCell<reload::Handle<LevelFilter, Registry>> = OnceCell::new();
+
+/// Sends log records to files in `dir` that rotate daily, and also to stdout
+/// if `stdout` is set. Can only be called once per process.
+pub fn init(dir: &Path, level: LogLevel, stdout: bool) -> Result<(), String> {
+    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let file = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(FILE_PREFIX)
+        .filename_suffix(FILE_SUFFIX)
+        .max_log_files(MAX_FILES)
+        .build(dir)
+        .map_err(|e| e.to_string())?;
+    let (filter, handle) = reload::Layer::new(level.filter());
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().with_writer(file).with_ansi(false))
+        .with(stdout.then(fmt::layer))
+        .try_init()
+        .map_err(|e| e.to_string())?;
+    let _ = LEVEL.set(handle);
+    Ok(())
+}
+
+/// Changes the level of a running logger.
+pub fn set_level(level: LogLevel) {
+    if let Some(handle) = LEVEL.get() {
+        if let Err(e) = handle.modify(|filter| *filter = level.filter()) {
+            tracing::warn!("Failed to change log level: {}", e);
+        }
+    }
+}
+
+/// The file currently written to, the newest one in `dir`.
+pub fn current_file(dir: &Path) -> Option<PathBuf> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with(FILE_PREFIX) && n.ends_with(FILE_SUFFIX))
+        })
+        // Names end in the date, so the newest sorts last
+        .max()
+}
+
+/// The last `lines` lines of the current log file.
+pub fn tail(dir: &Path, lines: usize) -> Result<Vec<String>, String> {
+    let Some(path) = current_file(dir) else {
+        return Ok(Vec::new());
+    };
+    let mut file = std::fs::File::open(&path).map_err(|e| e.to_string())?;
+    let len = file.metadata().map_err(|e| e.to_string())?.len();
+    let start = len.saturating_sub(TAIL_BYTES);
+    file.seek(SeekFrom::Start(start)).map_err(|e| e.to_string())?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+    let text = String::from_utf8_lossy(&bytes);
+    let mut all: Vec<&str> = text.lines().collect();
+    // Reading started mid-file, the first line is likely cut off
+    if start > 0 && !all.is_empty() {
+        all.remove(0);
+    }
+    Ok(all[all.len().saturating_sub(lines)..]
+        .iter()
+        .map(|l| l.to_string())
+        .collect())
+}
@@ -2,3 +2,16 @@ pub mod files;
 pub mod store;
 pub mod erabooru;
 pub mod tagging;
+pub mod queue;
+pub mod worker;
+pub mod settle;
+pub mod retry;
+#[cfg(feature = "gui")]
+pub mod secrets;
+pub mod events;
+pub mod hash_index;
+pub mod history;
+pub mod logging;
+pub mod watch_stats;
+pub mod watcher;
+pub mod upload;
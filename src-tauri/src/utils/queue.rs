@@ -0,0 +1,824 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::utils::erabooru::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueState {
+    Pending,
+    Uploading,
+    Tagging,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl QueueState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QueueState::Pending => "pending",
+            QueueState::Uploading => "uploading",
+            QueueState::Tagging => "tagging",
+            QueueState::Done => "done",
+            QueueState::Failed => "failed",
+            QueueState::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "pending" => Some(QueueState::Pending),
+            "uploading" => Some(QueueState::Uploading),
+            "tagging" => Some(QueueState::Tagging),
+            "done" => Some(QueueState::Done),
+            "failed" => Some(QueueState::Failed),
+            "cancelled" => Some(QueueState::Cancelled),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueSource {
+    Watch,
+    Upload,
+}
+
+impl QueueSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QueueSource::Watch => "watch",
+            QueueSource::Upload => "upload",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "watch" => Some(QueueSource::Watch),
+            "upload" => Some(QueueSource::Upload),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueItem {
+    pub id: i64,
+    pub path: PathBuf,
+    pub source: QueueSource,
+    pub target: String,
+    pub state: QueueState,
+    /// Bulk upload job the item belongs to, `None` for watched files
+    pub job: Option<i64>,
+    pub media_id: Option<String>,
+    /// Whether the server already had the file
+    pub duplicate: bool,
+    pub error: Option<Error>,
+    pub attempts: u32,
+    pub index_checks: u32,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Running,
+    Paused,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobState::Running => "running",
+            JobState::Paused => "paused",
+            JobState::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "running" => Some(JobState::Running),
+            "paused" => Some(JobState::Paused),
+            "cancelled" => Some(JobState::Cancelled),
+            _ => None,
+        }
+    }
+}
+
+/// A bulk folder upload and how far along its files are.
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: i64,
+    pub folder: String,
+    pub target: String,
+    pub state: JobState,
+    /// The folder is still being walked, more files may be added
+    pub listing: bool,
+    pub uploaded: u64,
+    pub duplicate: u64,
+    pub failed: u64,
+    pub cancelled: u64,
+    pub remaining: u64,
+    /// Unchanged files uploaded before, never queued
+    pub skipped: u64,
+    pub created_at: String,
+}
+
+/// Durable upload queue backed by a SQLite database in the app data dir.
+///
+/// Items move through `pending -> uploading -> tagging -> done`, or end up
+/// in `failed`, or in `cancelled` when their bulk upload job is cancelled.
+/// Anything left in `uploading` when the app stops is put back to `pending`
+/// by [`Queue::requeue_interrupted`] on the next launch, while `tagging`
+/// items keep their media id and only redo the tagging step.
+/// Several workers can drain the queue at once, [`Queue::claim_next`] never
+/// hands the same item to two of them.
+pub struct Queue {
+    conn: Mutex<Connection>,
+}
+
+const ITEM_COLUMNS: &str = "id, path, source, target, state, media_id, error, attempts, \
+                            index_checks, created_at, updated_at, job, duplicate";
+
+// Failed items that can be retried: the latest failure of each file, unless
+// the file is already queued again
+const RETRYABLE: &str = "state = 'failed'
+    AND id IN (SELECT MAX(id) FROM queue WHERE state = 'failed' GROUP BY path, target)
+    AND NOT EXISTS (
+        SELECT 1 FROM queue AS q
+        WHERE q.path = queue.path AND q.target = queue.target
+          AND q.state IN ('pending', 'uploading', 'tagging')
+    )";
+
+// Each entry upgrades the schema by one version, tracked in `user_version`
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE queue (
+         id INTEGER PRIMARY KEY AUTOINCREMENT,
+         path TEXT NOT NULL,
+         source TEXT NOT NULL,
+         state TEXT NOT NULL,
+         media_id TEXT,
+         error TEXT,
+         attempts INTEGER NOT NULL DEFAULT 0,
+         created_at TEXT NOT NULL,
+         updated_at TEXT NOT NULL
+     );
+     CREATE INDEX queue_state ON queue(state);
+     CREATE INDEX queue_path ON queue(path);",
+    // Items queued before multiple targets existed belong to the migrated legacy target
+    "ALTER TABLE queue ADD COLUMN target TEXT NOT NULL DEFAULT 'default';",
+    // `claimed` marks items a worker is busy with, `not_before` (unix ms) delays
+    // the next indexing check of tagging items without holding up a worker
+    "ALTER TABLE queue ADD COLUMN claimed INTEGER NOT NULL DEFAULT 0;
+     ALTER TABLE queue ADD COLUMN not_before INTEGER NOT NULL DEFAULT 0;
+     ALTER TABLE queue ADD COLUMN index_checks INTEGER NOT NULL DEFAULT 0;",
+    // Bulk uploads became jobs that can be paused and cancelled. `listing`
+    // is set while the job folder is still being walked for files.
+    "CREATE TABLE jobs (
+         id INTEGER PRIMARY KEY AUTOINCREMENT,
+         folder TEXT NOT NULL,
+         target TEXT NOT NULL,
+         state TEXT NOT NULL,
+         listing INTEGER NOT NULL DEFAULT 1,
+         created_at TEXT NOT NULL,
+         updated_at TEXT NOT NULL
+     );
+     ALTER TABLE queue ADD COLUMN job INTEGER REFERENCES jobs(id);
+     ALTER TABLE queue ADD COLUMN duplicate INTEGER NOT NULL DEFAULT 0;
+     CREATE INDEX queue_job ON queue(job);",
+    // Files a job left out because the hash index shows them uploaded already
+    "ALTER TABLE jobs ADD COLUMN skipped INTEGER NOT NULL DEFAULT 0;",
+];
+
+fn migrate(conn: &Connection) -> Result<(), String> {
+    let version: usize = conn
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    // Databases created before versioning already contain the first schema
+    let has_queue: bool = conn
+        .query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'queue'",
+            [],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    let version = if version == 0 && has_queue { 1 } else { version };
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        conn.execute_batch(migration).map_err(|e| e.to_string())?;
+        conn.pragma_update(None, "user_version", i + 1)
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+impl Queue {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let conn = Connection::open(path).map_err(|e| e.to_string())?;
+        conn.execute_batch("PRAGMA journal_mode = WAL;")
+            .map_err(|e| e.to_string())?;
+        migrate(&conn)?;
+        Ok(Queue {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Adds a file to the queue unless it is already waiting or in flight.
+    /// Returns `false` when the path was already queued.
+    pub fn enqueue(
+        &self,
+        path: &Path,
+        source: QueueSource,
+        target: &str,
+        job: Option<i64>,
+    ) -> Result<bool, String> {
+        let path_str = path.to_string_lossy();
+        let conn = self.conn.lock().unwrap();
+        let existing: Option<i64> = conn
+            .query_row(
+                "SELECT id FROM queue
+                 WHERE path = ?1 AND target = ?2 AND state IN ('pending', 'uploading', 'tagging')",
+                params![path_str, target],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        if existing.is_some() {
+            return Ok(false);
+        }
+        let now = now();
+        conn.execute(
+            "INSERT INTO queue (path, source, target, state, job, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)",
+            params![path_str, source.as_str(), target, QueueState::Pending.as_str(), job, now],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(true)
+    }
+
+    /// Follows a file or folder renamed inside a watched folder, so items
+    /// still waiting are uploaded under the new name. Finished, failed and
+    /// cancelled items keep the name they had at the time.
+    pub fn rename(&self, from: &Path, to: &Path, target: &str) -> Result<(), String> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE queue SET path = ?1 || substr(path, length(?2) + 1), updated_at = ?3
+             WHERE target = ?4 AND (path = ?2 OR substr(path, 1, length(?5)) = ?5)
+               AND state IN ('pending', 'uploading', 'tagging')",
+            params![
+                to.to_string_lossy(),
+                from.to_string_lossy(),
+                now(),
+                target,
+                folder_prefix(from)
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Releases everything workers were busy with when the app stopped and
+    /// puts items that were mid-upload back to pending.
+    pub fn requeue_interrupted(&self) -> Result<usize, String> {
+        let conn = self.conn.lock().unwrap();
+        let requeued = conn
+            .execute(
+                "UPDATE queue SET state = ?1, updated_at = ?2 WHERE state = ?3",
+                params![
+                    QueueState::Pending.as_str(),
+                    now(),
+                    QueueState::Uploading.as_str()
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+        conn.execute("UPDATE queue SET claimed = 0 WHERE claimed = 1", [])
+            .map_err(|e| e.to_string())?;
+        // Folders that were still being walked only got partly queued
+        conn.execute("UPDATE jobs SET listing = 0 WHERE listing = 1", [])
+            .map_err(|e| e.to_string())?;
+        Ok(requeued)
+    }
+
+    /// Takes the oldest unclaimed item that has work left, is due and is not
+    /// part of a paused job. The item stays claimed until it is marked done,
+    /// failed, tagging or deferred; pending items are marked as uploading on
+    /// the way.
+    pub fn claim_next(&self) -> Result<Option<QueueItem>, String> {
+        let conn = self.conn.lock().unwrap();
+        let item = conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM queue
+                     WHERE claimed = 0 AND state IN ('pending', 'tagging') AND not_before <= ?1
+                       AND (job IS NULL OR job NOT IN (SELECT id FROM jobs WHERE state = 'paused'))
+                     ORDER BY id LIMIT 1",
+                    ITEM_COLUMNS
+                ),
+                params![now_ms()],
+                row_to_item,
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+
+        let Some(mut item) = item else {
+            return Ok(None);
+        };
+        if item.state == QueueState::Pending {
+            item.state = QueueState::Uploading;
+            item.attempts += 1;
+        }
+        conn.execute(
+            "UPDATE queue SET claimed = 1, state = ?1, attempts = ?2, updated_at = ?3 WHERE id = ?4",
+            params![item.state.as_str(), item.attempts, now(), item.id],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(Some(item))
+    }
+
+    /// Everything still in flight, oldest first, followed by up to
+    /// `recent` of the most recently finished or failed items.
+    pub fn snapshot(&self, recent: u32) -> Result<Vec<QueueItem>, String> {
+        let conn = self.conn.lock().unwrap();
+        let mut items = query_items(
+            &conn,
+            "state IN ('pending', 'uploading', 'tagging') ORDER BY id",
+            params![],
+        )?;
+        items.extend(query_items(
+            &conn,
+            "state IN ('done', 'failed') ORDER BY updated_at DESC LIMIT ?1",
+            params![recent],
+        )?);
+        Ok(items)
+    }
+
+    pub fn mark_tagging(&self, id: i64, media_id: &str, duplicate: bool) -> Result<(), String> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE queue SET state = ?1, media_id = ?2, duplicate = ?3, error = NULL, claimed = 0,
+                 not_before = 0, index_checks = 0, updated_at = ?4
+             WHERE id = ?5",
+            params![QueueState::Tagging.as_str(), media_id, duplicate, now(), id],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Releases a tagging item whose media is not indexed yet so it is
+    /// checked again after `delay`, leaving the worker free for other items.
+    pub fn defer_tagging(&self, id: i64, delay: Duration) -> Result<(), String> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE queue SET claimed = 0, not_before = ?1, index_checks = index_checks + 1,
+                 updated_at = ?2
+             WHERE id = ?3",
+            params![now_ms() + delay.as_millis() as i64, now(), id],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Failed items that [`Queue::retry_failed`] would requeue.
+    pub fn failed(&self) -> Result<Vec<QueueItem>, String> {
+        let conn = self.conn.lock().unwrap();
+        query_items(&conn, &format!("{} ORDER BY id", RETRYABLE), params![])
+    }
+
+    /// Puts failed items back in the queue, all of them or only those in `ids`.
+    /// Items that already made it to the server only go through tagging
+    /// again. Files failed more than once are retried once, and files that
+    /// are already queued again are left alone. Returns how many were requeued.
+    pub fn retry_failed(&self, ids: Option<&[i64]>) -> Result<usize, String> {
+        let conn = self.conn.lock().unwrap();
+        let selection = match ids {
+            Some(ids) => format!(
+                "AND id IN ({})",
+                ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(",")
+            ),
+            None => String::new(),
+        };
+        conn.execute(
+            &format!(
+                "UPDATE queue SET state = CASE WHEN media_id IS NULL THEN ?1 ELSE ?2 END, error = NULL,
+                     claimed = 0, not_before = 0, index_checks = 0, updated_at = ?3
+                 WHERE {} {}",
+                RETRYABLE, selection
+            ),
+            params![QueueState::Pending.as_str(), QueueState::Tagging.as_str(), now()],
+        )
+        .map_err(|e| e.to_string())
+    }
+
+    pub fn create_job(&self, folder: &str, target: &str) -> Result<i64, String> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO jobs (folder, target, state, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?4)",
+            params![folder, target, JobState::Running.as_str(), now()],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    pub fn add_skipped(&self, job: i64, count: u64) -> Result<(), String> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE jobs SET skipped = skipped + ?1, updated_at = ?2 WHERE id = ?3",
+            params![count, now(), job],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    pub fn finish_listing(&self, job: i64) -> Result<(), String> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE jobs SET listing = 0, updated_at = ?1 WHERE id = ?2",
+            params![now(), job],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Pauses, resumes or cancels a job. Cancelling drops its items that
+    /// have not started uploading; items already in flight still finish.
+    /// Returns `false` for unknown or already cancelled jobs.
+    pub fn set_job_state(&self, job: i64, state: JobState) -> Result<bool, String> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        let changed = tx
+            .execute(
+                "UPDATE jobs SET state = ?1, updated_at = ?2 WHERE id = ?3 AND state != 'cancelled'",
+                params![state.as_str(), now(), job],
+            )
+            .map_err(|e| e.to_string())?;
+        if changed > 0 && state == JobState::Cancelled {
+            cancel_pending(&tx, job)?;
+        }
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(changed > 0)
+    }
+
+    /// Cancels items a cancelled job queued after it was cancelled.
+    pub fn cancel_leftovers(&self, job: i64) -> Result<(), String> {
+        let conn = self.conn.lock().unwrap();
+        cancel_pending(&conn, job)
+    }
+
+    pub fn job_state(&self, job: i64) -> Result<Option<JobState>, String> {
+        let conn = self.conn.lock().unwrap();
+        let state: Option<String> = conn
+            .query_row("SELECT state FROM jobs WHERE id = ?1", params![job], |row| row.get(0))
+            .optional()
+            .map_err(|e| e.to_string())?;
+        Ok(state.and_then(|s| JobState::parse(&s)))
+    }
+
+    /// Just the folder of a job, without counting its items.
+    pub fn job_folder(&self, job: i64) -> Result<Option<String>, String> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT folder FROM jobs WHERE id = ?1", params![job], |row| row.get(0))
+            .optional()
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn job(&self, job: i64) -> Result<Option<Job>, String> {
+        Ok(self.query_jobs("WHERE j.id = ?1", params![job])?.pop())
+    }
+
+    /// All jobs, newest first.
+    pub fn jobs(&self) -> Result<Vec<Job>, String> {
+        self.query_jobs("", params![])
+    }
+
+    fn query_jobs(&self, condition: &str, params: impl rusqlite::Params) -> Result<Vec<Job>, String> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT j.id, j.folder, j.target, j.state, j.listing, j.created_at, j.skipped,
+                     COUNT(CASE WHEN q.state = 'done' AND q.duplicate = 0 THEN 1 END),
+                     COUNT(CASE WHEN q.state = 'done' AND q.duplicate = 1 THEN 1 END),
+                     COUNT(CASE WHEN q.state = 'failed' THEN 1 END),
+                     COUNT(CASE WHEN q.state = 'cancelled' THEN 1 END),
+                     COUNT(CASE WHEN q.state IN ('pending', 'uploading', 'tagging') THEN 1 END)
+                 FROM jobs j LEFT JOIN queue q ON q.job = j.id
+                 {}
+                 GROUP BY j.id ORDER BY j.id DESC",
+                condition
+            ))
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params, |row| {
+                let state: String = row.get(3)?;
+                Ok(Job {
+                    id: row.get(0)?,
+                    folder: row.get(1)?,
+                    target: row.get(2)?,
+                    state: JobState::parse(&state).unwrap_or(JobState::Cancelled),
+                    listing: row.get(4)?,
+                    created_at: row.get(5)?,
+                    skipped: row.get(6)?,
+                    uploaded: row.get(7)?,
+                    duplicate: row.get(8)?,
+                    failed: row.get(9)?,
+                    cancelled: row.get(10)?,
+                    remaining: row.get(11)?,
+                })
+            })
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+    }
+
+    pub fn mark_done(&self, id: i64) -> Result<(), String> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE queue SET state = ?1, claimed = 0, updated_at = ?2 WHERE id = ?3",
+            params![QueueState::Done.as_str(), now(), id],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    pub fn mark_failed(&self, id: i64, error: &Error) -> Result<(), String> {
+        let error = serde_json::to_string(error).map_err(|e| e.to_string())?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE queue SET state = ?1, error = ?2, claimed = 0, updated_at = ?3 WHERE id = ?4",
+            params![QueueState::Failed.as_str(), error, now(), id],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+/// `folder` with a separator at the end, to match the paths inside it.
+pub(crate) fn folder_prefix(folder: &Path) -> String {
+    format!("{}{}", folder.to_string_lossy(), std::path::MAIN_SEPARATOR)
+}
+
+fn cancel_pending(conn: &Connection, job: i64) -> Result<(), String> {
+    conn.execute(
+        "UPDATE queue SET state = ?1, updated_at = ?2
+         WHERE job = ?3 AND state = 'pending' AND claimed = 0",
+        params![QueueState::Cancelled.as_str(), now(), job],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn query_items(
+    conn: &Connection,
+    condition: &str,
+    params: impl rusqlite::Params,
+) -> Result<Vec<QueueItem>, String> {
+    let mut stmt = conn
+        .prepare(&format!("SELECT {} FROM queue WHERE {}", ITEM_COLUMNS, condition))
+        .map_err(|e| e.to_string())?;
+    let rows = stmt.query_map(params, row_to_item).map_err(|e| e.to_string())?;
+    rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+}
+
+fn row_to_item(row: &rusqlite::Row) -> rusqlite::Result<QueueItem> {
+    let path: String = row.get(1)?;
+    let source: String = row.get(2)?;
+    let state: String = row.get(4)?;
+    let error: Option<String> = row.get(6)?;
+    Ok(QueueItem {
+        id: row.get(0)?,
+        path: PathBuf::from(path),
+        source: QueueSource::parse(&source).unwrap_or(QueueSource::Watch),
+        target: row.get(3)?,
+        state: QueueState::parse(&state).unwrap_or(QueueState::Failed),
+        media_id: row.get(5)?,
+        error: error.map(|e| parse_error(&e)),
+        attempts: row.get(7)?,
+        index_checks: row.get(8)?,
+        created_at: row.get(9)?,
+        updated_at: row.get(10)?,
+        job: row.get(11)?,
+        duplicate: row.get(12)?,
+    })
+}
+
+/// Reads an error stored as JSON, keeping the plain text of errors
+/// recorded before errors were structured.
+pub fn parse_error(stored: &str) -> Error {
+    serde_json::from_str(stored).unwrap_or_else(|_| Error::Other {
+        message: stored.to_string(),
+    })
+}
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339()
+}
+
+fn now_ms() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open() -> Queue {
+        Queue::open(Path::new(":memory:")).unwrap()
+    }
+
+    fn add(queue: &Queue, path: &str) -> i64 {
+        assert!(queue.enqueue(Path::new(path), QueueSource::Watch, "t", None).unwrap());
+        queue.conn.lock().unwrap().last_insert_rowid()
+    }
+
+    fn state(queue: &Queue, id: i64) -> QueueState {
+        let conn = queue.conn.lock().unwrap();
+        let state: String = conn
+            .query_row("SELECT state FROM queue WHERE id = ?1", params![id], |row| row.get(0))
+            .unwrap();
+        QueueState::parse(&state).unwrap()
+    }
+
+    fn path(queue: &Queue, id: i64) -> String {
+        let conn = queue.conn.lock().unwrap();
+        conn.query_row("SELECT path FROM queue WHERE id = ?1", params![id], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn migrates_unversioned_database() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(MIGRATIONS[0]).unwrap();
+        conn.execute(
+            "INSERT INTO queue (path, source, state, created_at, updated_at)
+             VALUES ('/a.png', 'watch', 'pending', '', '')",
+            [],
+        )
+        .unwrap();
+
+        migrate(&conn).unwrap();
+        let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        let target: String = conn.query_row("SELECT target FROM queue", [], |row| row.get(0)).unwrap();
+        assert_eq!(target, "default");
+
+        // Running again is a no-op
+        migrate(&conn).unwrap();
+    }
+
+    #[test]
+    fn enqueue_skips_paths_in_flight() {
+        let queue = open();
+        let id = add(&queue, "/a.png");
+        assert!(!queue.enqueue(Path::new("/a.png"), QueueSource::Watch, "t", None).unwrap());
+        assert!(queue.enqueue(Path::new("/a.png"), QueueSource::Watch, "other", None).unwrap());
+
+        queue.mark_done(id).unwrap();
+        add(&queue, "/a.png");
+    }
+
+    #[test]
+    fn claim_next_hands_out_each_item_once() {
+        let queue = open();
+        let a = add(&queue, "/a.png");
+        let b = add(&queue, "/b.png");
+
+        let first = queue.claim_next().unwrap().unwrap();
+        assert_eq!((first.id, first.state, first.attempts), (a, QueueState::Uploading, 1));
+        assert_eq!(queue.claim_next().unwrap().unwrap().id, b);
+        assert!(queue.claim_next().unwrap().is_none());
+    }
+
+    #[test]
+    fn requeue_interrupted_releases_claims() {
+        let queue = open();
+        let uploading = add(&queue, "/a.png");
+        let tagging = add(&queue, "/b.png");
+        queue.claim_next().unwrap();
+        queue.claim_next().unwrap();
+        queue.mark_tagging(tagging, "m1", false).unwrap();
+        queue.claim_next().unwrap();
+
+        assert_eq!(queue.requeue_interrupted().unwrap(), 1);
+        assert_eq!(state(&queue, uploading), QueueState::Pending);
+        assert_eq!(state(&queue, tagging), QueueState::Tagging);
+        assert_eq!(queue.claim_next().unwrap().unwrap().id, uploading);
+        assert_eq!(queue.claim_next().unwrap().unwrap().id, tagging);
+    }
+
+    #[test]
+    fn claim_next_waits_for_deferred_tagging() {
+        let queue = open();
+        let id = add(&queue, "/a.png");
+        queue.claim_next().unwrap();
+        queue.mark_tagging(id, "m1", false).unwrap();
+        queue.claim_next().unwrap();
+        queue.defer_tagging(id, Duration::from_secs(60)).unwrap();
+        assert!(queue.claim_next().unwrap().is_none());
+
+        queue.defer_tagging(id, Duration::ZERO).unwrap();
+        let item = queue.claim_next().unwrap().unwrap();
+        assert_eq!((item.state, item.media_id.as_deref()), (QueueState::Tagging, Some("m1")));
+        // Checking the index again is not another upload attempt
+        assert_eq!(item.attempts, 1);
+        assert_eq!(item.index_checks, 2);
+    }
+
+    #[test]
+    fn claim_next_skips_paused_jobs() {
+        let queue = open();
+        let job = queue.create_job("/folder", "t").unwrap();
+        queue.enqueue(Path::new("/folder/a.png"), QueueSource::Upload, "t", Some(job)).unwrap();
+        queue.set_job_state(job, JobState::Paused).unwrap();
+        assert!(queue.claim_next().unwrap().is_none());
+
+        queue.set_job_state(job, JobState::Running).unwrap();
+        assert!(queue.claim_next().unwrap().is_some());
+    }
+
+    #[test]
+    fn retry_failed_requeues_latest_failure_per_file() {
+        let queue = open();
+        let old = add(&queue, "/a.png");
+        queue.mark_failed(old, &Error::config("test")).unwrap();
+        let new = add(&queue, "/a.png");
+        queue.mark_failed(new, &Error::config("test")).unwrap();
+
+        assert_eq!(queue.retry_failed(None).unwrap(), 1);
+        assert_eq!(state(&queue, old), QueueState::Failed);
+        assert_eq!(state(&queue, new), QueueState::Pending);
+    }
+
+    #[test]
+    fn retry_failed_leaves_files_queued_again() {
+        let queue = open();
+        let failed = add(&queue, "/a.png");
+        queue.mark_failed(failed, &Error::config("test")).unwrap();
+        add(&queue, "/a.png");
+
+        assert_eq!(queue.retry_failed(None).unwrap(), 0);
+        assert_eq!(state(&queue, failed), QueueState::Failed);
+    }
+
+    #[test]
+    fn failed_lists_what_retry_failed_requeues() {
+        let queue = open();
+        let old = add(&queue, "/a.png");
+        queue.mark_failed(old, &Error::config("test")).unwrap();
+        let new = add(&queue, "/a.png");
+        queue.mark_failed(new, &Error::config("test")).unwrap();
+        let queued = add(&queue, "/b.png");
+        queue.mark_failed(queued, &Error::config("test")).unwrap();
+        add(&queue, "/b.png");
+
+        let ids: Vec<i64> = queue.failed().unwrap().into_iter().map(|i| i.id).collect();
+        assert_eq!(ids, [new]);
+        // Retrying the listed ids requeues all of them
+        assert_eq!(queue.retry_failed(Some(&ids)).unwrap(), ids.len());
+    }
+
+    #[test]
+    fn retry_failed_only_tags_uploaded_items_again() {
+        let queue = open();
+        let id = add(&queue, "/a.png");
+        queue.claim_next().unwrap();
+        queue.mark_tagging(id, "m1", false).unwrap();
+        queue.mark_failed(id, &Error::config("test")).unwrap();
+        let other = add(&queue, "/b.png");
+        queue.mark_failed(other, &Error::config("test")).unwrap();
+
+        assert_eq!(queue.retry_failed(Some(&[id])).unwrap(), 1);
+        assert_eq!(state(&queue, id), QueueState::Tagging);
+        assert_eq!(state(&queue, other), QueueState::Failed);
+    }
+
+    #[test]
+    fn rename_moves_files_and_folders() {
+        let queue = open();
+        let sep = std::path::MAIN_SEPARATOR;
+        let file = add(&queue, "/w/a.png");
+        let nested = add(&queue, &format!("/w/sub{}b.png", sep));
+        let sibling = add(&queue, &format!("/w/sub2{}c.png", sep));
+        let done = add(&queue, &format!("/w/sub{}d.png", sep));
+        queue.mark_done(done).unwrap();
+        let failed = add(&queue, &format!("/w/sub{}e.png", sep));
+        queue.mark_failed(failed, &Error::config("test")).unwrap();
+
+        queue.rename(Path::new("/w/a.png"), Path::new("/w/z.png"), "t").unwrap();
+        queue.rename(Path::new("/w/sub"), Path::new("/w/new"), "t").unwrap();
+
+        assert_eq!(path(&queue, file), "/w/z.png");
+        assert_eq!(path(&queue, nested), format!("/w/new{}b.png", sep));
+        assert_eq!(path(&queue, sibling), format!("/w/sub2{}c.png", sep));
+        // Finished items are history and keep their old path
+        assert_eq!(path(&queue, done), format!("/w/sub{}d.png", sep));
+        assert_eq!(path(&queue, failed), format!("/w/sub{}e.png", sep));
+    }
+}
@@ -0,0 +1,221 @@
+use reqwest::blocking::{RequestBuilder, Response};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::warn;
+
+use crate::utils::erabooru::Error;
+
+/// How requests to the EraBooru server are retried when they fail for
+/// reasons that are likely to go away: connection problems, timeouts and
+/// the HTTP statuses listed in `retry_statuses`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Total number of tries, including the first one
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+    /// Random spread applied to each delay, as a fraction of it (0.0 - 1.0)
+    pub jitter: f64,
+    pub retry_statuses: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay_ms: 500,
+            max_delay_ms: 30_000,
+            jitter: 0.2,
+            retry_statuses: vec![429, 502, 503, 504],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Sends the request built by `build`, rebuilding and resending it after a
+    /// delay for as long as it fails transiently and attempts are left. The
+    /// last response or error is returned as is once attempts run out, or
+    /// when the server asks to wait longer than `max_delay_ms`.
+    pub fn send<F>(&self, mut build: F) -> Result<Response, Error>
+    where
+        F: FnMut() -> Result<RequestBuilder, Error>,
+    {
+        let mut attempt = 1;
+        loop {
+            let result = build()?.send();
+            let (delay, reason) = match &result {
+                Ok(resp) if self.is_retryable_status(resp.status()) => {
+                    let delay = match retry_after(resp) {
+                        // Not worth holding a worker for, the item fails and can be retried later
+                        Some(wait) if wait > Duration::from_millis(self.max_delay_ms) => {
+                            warn!(
+                                "Request failed (status {}), server asked to wait {:?}, giving up",
+                                resp.status(),
+                                wait
+                            );
+                            return result.map_err(Error::from);
+                        }
+                        Some(wait) => wait,
+                        None => self.backoff(attempt),
+                    };
+                    (delay, format!("status {}", resp.status()))
+                }
+                Err(e) if is_transient(e) => (self.backoff(attempt), e.to_string()),
+                _ => return result.map_err(Error::from),
+            };
+            if attempt >= self.max_attempts {
+                return result.map_err(Error::from);
+            }
+            warn!(
+                "Request failed ({}), retrying in {:?} (attempt {}/{})",
+                reason, delay, attempt, self.max_attempts
+            );
+            std::thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    fn is_retryable_status(&self, status: StatusCode) -> bool {
+        self.retry_statuses.contains(&status.as_u16())
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_delay_ms
+            .saturating_mul(1u64 << (attempt - 1).min(32))
+            .min(self.max_delay_ms);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = 1.0 + jitter * (fastrand::f64() * 2.0 - 1.0);
+        Duration::from_millis((exp as f64 * factor) as u64)
+    }
+}
+
+fn is_transient(e: &reqwest::Error) -> bool {
+    e.is_connect() || e.is_timeout() || e.is_request()
+}
+
+/// Reads `Retry-After`, given either in seconds or as an HTTP date.
+fn retry_after(resp: &Response) -> Option<Duration> {
+    let value = resp.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    parse_retry_after(value, chrono::Utc::now())
+}
+
+fn parse_retry_after(value: &str, now: chrono::DateTime<chrono::Utc>) -> Option<Duration> {
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    let wait = date.signed_duration_since(now);
+    Some(wait.to_std().unwrap_or(Duration::ZERO))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn policy(jitter: f64) -> RetryPolicy {
+        RetryPolicy {
+            base_delay_ms: 100,
+            max_delay_ms: 1000,
+            jitter,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Answers every request with `response`, counting them.
+    fn serve(response: &'static str) -> (String, Arc<AtomicUsize>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let _ = stream.read(&mut [0; 1024]);
+                counter.fetch_add(1, Ordering::SeqCst);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        (url, requests)
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let policy = policy(0.0);
+        let delays: Vec<u64> = (1..=6).map(|a| policy.backoff(a).as_millis() as u64).collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+        // Large attempt counts must not overflow
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn backoff_jitter_stays_in_range() {
+        let policy = policy(0.5);
+        for _ in 0..100 {
+            let delay = policy.backoff(3).as_millis();
+            assert!((200..=600).contains(&delay), "{}", delay);
+        }
+    }
+
+    #[test]
+    fn retries_listed_statuses_until_attempts_run_out() {
+        let (url, requests) = serve(
+            "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        );
+        let client = reqwest::blocking::Client::new();
+        let policy = RetryPolicy { max_attempts: 3, ..policy(0.0) };
+        let resp = policy.send(|| Ok(client.get(&url))).unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn other_statuses_are_not_retried() {
+        let (url, requests) =
+            serve("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+        let client = reqwest::blocking::Client::new();
+        let resp = policy(0.0).send(|| Ok(client.get(&url))).unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn retry_after_in_seconds() {
+        let now = chrono::Utc::now();
+        assert_eq!(parse_retry_after("120", now), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 5 ", now), Some(Duration::from_secs(5)));
+        assert_eq!(parse_retry_after("soon", now), None);
+        assert_eq!(parse_retry_after("-1", now), None);
+    }
+
+    #[test]
+    fn retry_after_as_date() {
+        let now = chrono::DateTime::parse_from_rfc2822("Wed, 21 Oct 2015 07:28:00 GMT")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        // Dates in the past mean right away
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:27:00 GMT", now), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn gives_up_when_retry_after_exceeds_max_delay() {
+        let (url, requests) = serve(
+            "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 86400\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        );
+        let client = reqwest::blocking::Client::new();
+        let started = std::time::Instant::now();
+        let resp = policy(0.0).send(|| Ok(client.get(&url))).unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
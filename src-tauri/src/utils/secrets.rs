@@ -0,0 +1,138 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use serde::{Deserialize, Serialize};
+use std::{fs, io::Write, path::Path};
+
+/// Ciphertext as written to the settings store.
+#[derive(Serialize, Deserialize)]
+pub struct Sealed {
+    nonce: String,
+    data: String,
+}
+
+/// Encrypts secrets with a key kept in a local master key file, created with
+/// a random key on first use. Anyone who can read the key file can decrypt
+/// the secrets, the point is keeping them out of the plain JSON settings.
+pub struct SecretBox {
+    cipher: ChaCha20Poly1305,
+}
+
+impl SecretBox {
+    pub fn open_or_create(key_path: &Path) -> Result<Self, String> {
+        let key = match fs::read(key_path) {
+            Ok(bytes) => {
+                if bytes.len() != 32 {
+                    return Err(format!("invalid master key file: {}", key_path.display()));
+                }
+                *Key::from_slice(&bytes)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let key = ChaCha20Poly1305::generate_key(&mut OsRng);
+                write_key(key_path, &key).map_err(|e| format!("failed to write master key: {}", e))?;
+                key
+            }
+            Err(e) => return Err(format!("failed to read master key: {}", e)),
+        };
+        Ok(SecretBox {
+            cipher: ChaCha20Poly1305::new(&key),
+        })
+    }
+
+    pub fn seal(&self, plain: &[u8]) -> Result<Sealed, String> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let data = self
+            .cipher
+            .encrypt(&nonce, plain)
+            .map_err(|_| "failed to encrypt secrets".to_string())?;
+        Ok(Sealed {
+            nonce: STANDARD.encode(nonce),
+            data: STANDARD.encode(data),
+        })
+    }
+
+    pub fn open(&self, sealed: &Sealed) -> Result<Vec<u8>, String> {
+        let nonce = STANDARD.decode(&sealed.nonce).map_err(|e| e.to_string())?;
+        let data = STANDARD.decode(&sealed.data).map_err(|e| e.to_string())?;
+        if nonce.len() != 12 {
+            return Err("invalid secrets nonce".into());
+        }
+        self.cipher
+            .decrypt(Nonce::from_slice(&nonce), data.as_slice())
+            .map_err(|_| "failed to decrypt secrets, was the master key replaced?".to_string())
+    }
+}
+
+fn write_key(path: &Path, key: &Key) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("secrets-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn seal_and_open_round_trip() {
+        let dir = temp_dir("round-trip");
+        let secrets = SecretBox::open_or_create(&dir.join("master.key")).unwrap();
+        let sealed = secrets.seal(b"hunter2").unwrap();
+        assert_ne!(STANDARD.decode(&sealed.data).unwrap(), b"hunter2");
+        assert_eq!(secrets.open(&sealed).unwrap(), b"hunter2");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn other_key_cannot_open() {
+        let dir = temp_dir("other-key");
+        let sealed = SecretBox::open_or_create(&dir.join("a.key")).unwrap().seal(b"hunter2").unwrap();
+        let other = SecretBox::open_or_create(&dir.join("b.key")).unwrap();
+        assert!(other.open(&sealed).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_key_of_wrong_length() {
+        let dir = temp_dir("bad-key");
+        fs::create_dir_all(&dir).unwrap();
+        let key_path = dir.join("master.key");
+        fs::write(&key_path, [0u8; 16]).unwrap();
+        assert!(SecretBox::open_or_create(&key_path).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn creates_key_once_and_reuses_it() {
+        let dir = temp_dir("reuse");
+        // Missing parent folders are created along with the key
+        let key_path = dir.join("nested").join("master.key");
+        let sealed = SecretBox::open_or_create(&key_path).unwrap().seal(b"hunter2").unwrap();
+        let key = fs::read(&key_path).unwrap();
+        assert_eq!(key.len(), 32);
+
+        let reopened = SecretBox::open_or_create(&key_path).unwrap();
+        assert_eq!(fs::read(&key_path).unwrap(), key);
+        assert_eq!(reopened.open(&sealed).unwrap(), b"hunter2");
+        // An existing key file is never overwritten
+        assert!(write_key(&key_path, Key::from_slice(&key)).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+struct Pending {
+    size: u64,
+    modified: Option<SystemTime>,
+    changed_at: Instant,
+}
+
+/// Holds back newly detected files until they stop changing, so files that
+/// are still being copied are not read half-written.
+///
+/// A file is considered settled once no events arrived for it and its size
+/// and modification time stayed the same for the whole quiet interval.
+pub struct Settler {
+    quiet: Duration,
+    pending: HashMap<PathBuf, Pending>,
+}
+
+impl Settler {
+    pub fn new(quiet: Duration) -> Self {
+        Settler {
+            quiet,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Starts tracking a file, or restarts its quiet interval if already tracked.
+    pub fn touch(&mut self, path: PathBuf) {
+        let (size, modified) = stat(&path);
+        self.pending.insert(
+            path,
+            Pending {
+                size,
+                modified,
+                changed_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Restarts the quiet interval of a file only if it is already tracked.
+    pub fn touch_if_pending(&mut self, path: PathBuf) {
+        if self.pending.contains_key(&path) {
+            self.touch(path);
+        }
+    }
+
+    pub fn forget(&mut self, path: &Path) {
+        self.pending.remove(path);
+    }
+
+    /// Returns files that have been quiet long enough and stops tracking them.
+    /// Files that disappeared in the meantime are dropped.
+    pub fn take_settled(&mut self) -> Vec<PathBuf> {
+        let mut settled = Vec::new();
+        let quiet = self.quiet;
+        self.pending.retain(|path, p| {
+            // Only looked at once the interval is over, a catch-up scan can
+            // leave a great many files in here
+            if p.changed_at.elapsed() < quiet {
+                return true;
+            }
+            if !path.is_file() {
+                return false;
+            }
+            let (size, modified) = stat(path);
+            if size != p.size || modified != p.modified {
+                p.size = size;
+                p.modified = modified;
+                p.changed_at = Instant::now();
+                return true;
+            }
+            settled.push(path.clone());
+            false
+        });
+        settled
+    }
+}
+
+fn stat(path: &Path) -> (u64, Option<SystemTime>) {
+    match std::fs::metadata(path) {
+        Ok(m) => (m.len(), m.modified().ok()),
+        Err(_) => (0, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const QUIET: Duration = Duration::from_millis(100);
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("settle-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn settles_after_quiet_interval() {
+        let dir = temp_dir("quiet");
+        let path = dir.join("a.png");
+        std::fs::write(&path, b"data").unwrap();
+
+        let mut settler = Settler::new(QUIET);
+        settler.touch(path.clone());
+        assert!(settler.take_settled().is_empty());
+        std::thread::sleep(QUIET);
+        assert_eq!(settler.take_settled(), vec![path]);
+        assert!(settler.take_settled().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn growing_file_restarts_interval() {
+        let dir = temp_dir("growing");
+        let path = dir.join("a.png");
+        std::fs::write(&path, b"data").unwrap();
+
+        let mut settler = Settler::new(QUIET);
+        settler.touch(path.clone());
+        std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"more").unwrap();
+        std::thread::sleep(QUIET);
+        assert!(settler.take_settled().is_empty());
+        std::thread::sleep(QUIET);
+        assert_eq!(settler.take_settled(), vec![path]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn drops_forgotten_and_deleted_files() {
+        let dir = temp_dir("dropped");
+        let (kept, forgotten, deleted) = (dir.join("a.png"), dir.join("b.png"), dir.join("c.png"));
+        for path in [&kept, &forgotten, &deleted] {
+            std::fs::write(path, b"data").unwrap();
+        }
+
+        let mut settler = Settler::new(QUIET);
+        for path in [&kept, &forgotten, &deleted] {
+            settler.touch(path.clone());
+        }
+        settler.forget(&forgotten);
+        std::fs::remove_file(&deleted).unwrap();
+        std::thread::sleep(QUIET);
+        assert_eq!(settler.take_settled(), vec![kept]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn touch_if_pending_ignores_unknown_files() {
+        let dir = temp_dir("pending");
+        let path = dir.join("a.png");
+        std::fs::write(&path, b"data").unwrap();
+
+        let mut settler = Settler::new(Duration::ZERO);
+        settler.touch_if_pending(path.clone());
+        assert!(settler.take_settled().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
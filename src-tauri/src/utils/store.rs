@@ -1,33 +1,199 @@
 use serde::{Deserialize, Serialize};
-use tauri_plugin_store::StoreExt;
 
+use crate::utils::logging::LogLevel;
+use crate::utils::retry::RetryPolicy;
+use crate::utils::tagging;
+
+/// Settings saved by the app through the Tauri store plugin
+#[cfg(feature = "gui")]
+mod app_store;
+#[cfg(feature = "gui")]
+pub use app_store::{encrypt_plain_secrets, get_settings, save_settings};
+
+/// What an auto-tag rule's pattern is matched against.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleKind {
+    /// Any parent folder named exactly the pattern, the only kind older
+    /// settings have
+    #[default]
+    Folder,
+    /// A glob such as `**/Screenshots/**` against the path inside the watched folder
+    Glob,
+    /// A glob such as `*.gif` against the file name
+    Filename,
+    /// A regex against the path inside the watched folder
+    Regex,
+}
 
 #[derive(Default, Serialize, Deserialize, Clone)]
 pub struct AutoTagRule {
-    pub folder: String,
+    #[serde(default)]
+    pub kind: RuleKind,
+    #[serde(alias = "folder")]
+    pub pattern: String,
     pub tags: String,
+    #[serde(default)]
+    pub case_insensitive: bool,
+}
+
+/// How requests to the EraBooru API authenticate. Never applied to the
+/// pre-signed upload URLs, which carry their own authorization.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Auth {
+    #[default]
+    None,
+    Bearer { token: String },
+    Basic { username: String, password: String },
+    Header { name: String, value: String },
+}
+
+/// How a folder is watched for new files.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchMode {
+    /// Change notifications from the operating system
+    #[default]
+    Native,
+    /// Rescanning the folder periodically. Slower, but also sees changes made
+    /// by other machines on network shares, which notifications miss.
+    Poll,
 }
 
 #[derive(Default, Serialize, Deserialize, Clone)]
-pub struct Settings {
+pub struct WatchTarget {
+    #[serde(default)]
+    pub id: String,
     pub folder: String,
     pub server: String,
     #[serde(default)]
     pub auto_tags: Vec<AutoTagRule>,
     #[serde(default)]
     pub override_upload_date: bool,
+    #[serde(default)]
+    pub recursive: bool,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// How long a new file must stay unchanged before it is uploaded
+    #[serde(default = "default_settle_ms")]
+    pub settle_ms: u64,
+    #[serde(default)]
+    pub auth: Auth,
+    /// Queue files that were added while nobody was watching when the watcher starts
+    #[serde(default = "default_true")]
+    pub catch_up: bool,
+    #[serde(default)]
+    pub watch_mode: WatchMode,
+    #[serde(default = "default_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Settings {
+    #[serde(default)]
+    pub targets: Vec<WatchTarget>,
+    /// Number of files uploaded and tagged in parallel
+    #[serde(default = "default_upload_workers")]
+    pub upload_workers: usize,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+    /// Applies to API calls; file uploads get extra time based on their size
+    #[serde(default = "default_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+    #[serde(default)]
+    pub log_level: LogLevel,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            targets: Vec::new(),
+            upload_workers: default_upload_workers(),
+            retry: RetryPolicy::default(),
+            connect_timeout_secs: default_connect_timeout_secs(),
+            request_timeout_secs: default_request_timeout_secs(),
+            log_level: LogLevel::default(),
+        }
+    }
 }
 
-pub fn get_settings(app: &tauri::AppHandle) -> Result<Settings, String> {
-    let store = app.store("store.json").map_err(|e| e.to_string())?;
+impl Settings {
+    pub fn target(&self, id: &str) -> Option<&WatchTarget> {
+        self.targets.iter().find(|t| t.id == id)
+    }
 
-    let settings_value = store.get("settings");
-    if let Some(settings_json) = settings_value {
-        if let Ok(settings) = serde_json::from_value::<Settings>(settings_json) {
-            return Ok(settings);
+    pub fn validate(&self) -> Result<(), String> {
+        for (i, target) in self.targets.iter().enumerate() {
+            if target.id.is_empty() {
+                return Err(format!("watch target {} has no id", i + 1));
+            }
+            if self.targets[..i].iter().any(|t| t.id == target.id) {
+                return Err(format!("duplicate watch target id: {}", target.id));
+            }
+            if target.watch_mode == WatchMode::Poll && target.poll_interval_secs == 0 {
+                return Err(format!("{}: poll interval must be at least 1 second", target.folder));
+            }
+            match &target.auth {
+                Auth::Basic { username, .. } if username.is_empty() => {
+                    return Err(format!("{}: username is empty", target.folder));
+                }
+                Auth::Header { name, .. }
+                    if reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err() =>
+                {
+                    return Err(format!("{}: invalid header name '{}'", target.folder, name));
+                }
+                _ => {}
+            }
+            for rule in &target.auto_tags {
+                tagging::validate(rule)
+                    .map_err(|e| format!("{}: auto-tag rule '{}': {}", target.folder, rule.pattern, e))?;
+            }
+        }
+        if self.upload_workers == 0 || self.upload_workers > MAX_UPLOAD_WORKERS {
+            return Err(format!("upload workers must be between 1 and {}", MAX_UPLOAD_WORKERS));
         }
+        Ok(())
     }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_settle_ms() -> u64 {
+    2000
+}
+
+fn default_poll_interval_secs() -> u64 {
+    10
+}
+
+fn default_upload_workers() -> usize {
+    4
+}
 
-    // Return default settings if none found
-    Ok(Settings::default())
+fn default_connect_timeout_secs() -> u64 {
+    10
+}
+
+fn default_request_timeout_secs() -> u64 {
+    30
+}
+
+pub const MAX_UPLOAD_WORKERS: usize = 32;
+
+/// Supplies the settings upload workers use. They are asked again for every
+/// file, so changes apply without restarting the workers.
+pub trait SettingsSource: Send + Sync {
+    fn settings(&self) -> Result<Settings, String>;
+}
+
+/// Fixed settings, e.g. from the command line tool's config file
+impl SettingsSource for Settings {
+    fn settings(&self) -> Result<Settings, String> {
+        Ok(self.clone())
+    }
 }
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tauri::Manager;
+use tauri_plugin_store::StoreExt;
+use tracing::error;
+
+use super::{Auth, Settings, SettingsSource, WatchTarget};
+use crate::utils::secrets::{Sealed, SecretBox};
+
+const LEGACY_TARGET_ID: &str = "default";
+
+const STORE_FILE: &str = "store.json";
+const SETTINGS_KEY: &str = "settings";
+const SECRETS_KEY: &str = "secrets";
+
+// Settings are read for every upload, this keeps the error from being logged each time
+static SECRETS_UNREADABLE: AtomicBool = AtomicBool::new(false);
+
+impl Auth {
+    /// The part of the credentials that is kept in the encrypted secrets
+    /// section instead of the plain settings.
+    fn secret_mut(&mut self) -> Option<&mut String> {
+        match self {
+            Auth::None => None,
+            Auth::Bearer { token } => Some(token),
+            Auth::Basic { password, .. } => Some(password),
+            Auth::Header { value, .. } => Some(value),
+        }
+    }
+
+    fn same_kind(&self, other: &Auth) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+impl SettingsSource for tauri::AppHandle {
+    fn settings(&self) -> Result<Settings, String> {
+        get_settings_with_secrets(self)
+    }
+}
+
+/// Settings as shown to the user: credential secrets are left empty.
+/// Use [`get_settings_with_secrets`] where requests are actually sent.
+pub fn get_settings(app: &tauri::AppHandle) -> Result<Settings, String> {
+    let mut settings = read_settings(app)?;
+    for target in &mut settings.targets {
+        if let Some(secret) = target.auth.secret_mut() {
+            secret.clear();
+        }
+    }
+    Ok(settings)
+}
+
+/// Settings with the credential secrets decrypted and filled in.
+pub fn get_settings_with_secrets(app: &tauri::AppHandle) -> Result<Settings, String> {
+    let mut settings = read_settings(app)?;
+    // Targets that need a secret then fail on their own, see `ErabooruClient::new`
+    let mut secrets = match read_secrets(app) {
+        Ok(secrets) => {
+            SECRETS_UNREADABLE.store(false, Ordering::Relaxed);
+            secrets
+        }
+        Err(e) => {
+            if !SECRETS_UNREADABLE.swap(true, Ordering::Relaxed) {
+                error!("Failed to read saved credentials, they need to be entered again: {}", e);
+            }
+            HashMap::new()
+        }
+    };
+    for target in &mut settings.targets {
+        if let Some(mut stored) = secrets.remove(&target.id) {
+            if stored.same_kind(&target.auth) {
+                if let (Some(secret), Some(stored)) = (target.auth.secret_mut(), stored.secret_mut()) {
+                    *secret = std::mem::take(stored);
+                }
+            }
+        }
+    }
+    Ok(settings)
+}
+
+/// Writes the settings, moving credential secrets to the encrypted section.
+/// An empty secret keeps the one saved before, as long as the kind of
+/// authentication did not change.
+pub fn save_settings(app: &tauri::AppHandle, settings: &Settings) -> Result<(), String> {
+    let mut plain = settings.clone();
+    let current = get_settings_with_secrets(app)?;
+    let mut secrets = HashMap::new();
+    for target in &mut plain.targets {
+        let Some(secret) = target.auth.secret_mut() else {
+            continue;
+        };
+        let mut value = std::mem::take(secret);
+        if value.is_empty() {
+            if let Some(mut saved) = current.target(&target.id).map(|t| t.auth.clone()) {
+                if saved.same_kind(&target.auth) {
+                    value = saved.secret_mut().map(std::mem::take).unwrap_or_default();
+                }
+            }
+        }
+        if value.is_empty() && !matches!(target.auth, Auth::Basic { .. }) {
+            return Err(format!("{}: credentials are incomplete", target.folder));
+        }
+        let mut stored = target.auth.clone();
+        if let Some(secret) = stored.secret_mut() {
+            *secret = value;
+        }
+        secrets.insert(target.id.clone(), stored);
+    }
+
+    let store = app.store(STORE_FILE).map_err(|e| e.to_string())?;
+    if secrets.is_empty() {
+        store.delete(SECRETS_KEY);
+    } else {
+        let json = serde_json::to_vec(&secrets).map_err(|e| e.to_string())?;
+        let sealed = secret_box(app)?.seal(&json)?;
+        store.set(SECRETS_KEY, serde_json::to_value(&sealed).map_err(|e| e.to_string())?);
+    }
+    store.set(
+        SETTINGS_KEY,
+        serde_json::to_value(&plain).map_err(|e| e.to_string())?,
+    );
+    store.save().map_err(|e| e.to_string())
+}
+
+/// Moves secrets that older versions saved in plain text into the encrypted
+/// section. Returns whether there were any.
+pub fn encrypt_plain_secrets(app: &tauri::AppHandle) -> Result<bool, String> {
+    let mut settings = read_settings(app)?;
+    let found = settings
+        .targets
+        .iter_mut()
+        .any(|t| t.auth.secret_mut().is_some_and(|s| !s.is_empty()));
+    if found {
+        save_settings(app, &settings)?;
+    }
+    Ok(found)
+}
+
+fn secret_box(app: &tauri::AppHandle) -> Result<SecretBox, String> {
+    let dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    SecretBox::open_or_create(&dir.join("secret.key"))
+}
+
+fn read_secrets(app: &tauri::AppHandle) -> Result<HashMap<String, Auth>, String> {
+    let store = app.store(STORE_FILE).map_err(|e| e.to_string())?;
+    let Some(value) = store.get(SECRETS_KEY) else {
+        return Ok(HashMap::new());
+    };
+    let sealed: Sealed = serde_json::from_value(value).map_err(|e| e.to_string())?;
+    let json = secret_box(app)?.open(&sealed)?;
+    serde_json::from_slice(&json).map_err(|e| e.to_string())
+}
+
+/// Settings exactly as stored. Secrets saved in plain text by older versions
+/// are in there until [`encrypt_plain_secrets`] runs at startup.
+fn read_settings(app: &tauri::AppHandle) -> Result<Settings, String> {
+    let store = app.store(STORE_FILE).map_err(|e| e.to_string())?;
+
+    let settings_value = store.get(SETTINGS_KEY);
+    if let Some(settings_json) = settings_value {
+        if let Ok(mut settings) = serde_json::from_value::<Settings>(settings_json.clone()) {
+            if settings.targets.is_empty() {
+                // Older versions stored a single folder/server pair at the top level
+                if let Ok(mut legacy) = serde_json::from_value::<WatchTarget>(settings_json) {
+                    if !legacy.folder.is_empty() || !legacy.server.is_empty() {
+                        legacy.id = LEGACY_TARGET_ID.into();
+                        settings.targets.push(legacy);
+                    }
+                }
+            }
+            return Ok(settings);
+        }
+    }
+
+    // Return default settings if none found
+    Ok(Settings::default())
+}
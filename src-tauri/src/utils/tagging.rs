@@ -1,27 +1,223 @@
-use std::path::{Path, Component};
-use crate::utils::store::AutoTagRule;
-
-pub fn tags_for_path(path: &Path, rules: &[AutoTagRule]) -> Vec<String> {
-    let mut components = Vec::new();
-    if let Some(parent) = path.parent() {
-        for comp in parent.components() {
-            if let Component::Normal(os) = comp {
-                if let Some(s) = os.to_str() {
-                    components.push(s.to_string());
-                }
-            }
+use globset::{Glob, GlobBuilder, GlobMatcher};
+use regex::{Regex, RegexBuilder};
+use std::path::{Component, Path};
+use tracing::warn;
+
+use crate::utils::store::{AutoTagRule, RuleKind};
+
+/// A rule's pattern, compiled.
+enum Matcher {
+    Folder { name: String, case_insensitive: bool },
+    Glob(GlobMatcher),
+    Filename(GlobMatcher),
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn new(rule: &AutoTagRule) -> Result<Self, String> {
+        let glob = |pattern: &str| -> Result<Glob, String> {
+            GlobBuilder::new(pattern)
+                .case_insensitive(rule.case_insensitive)
+                .literal_separator(true)
+                .build()
+                .map_err(|e| e.to_string())
+        };
+        Ok(match rule.kind {
+            RuleKind::Folder => Matcher::Folder {
+                name: rule.pattern.clone(),
+                case_insensitive: rule.case_insensitive,
+            },
+            RuleKind::Glob => Matcher::Glob(glob(&rule.pattern)?.compile_matcher()),
+            RuleKind::Filename => Matcher::Filename(glob(&rule.pattern)?.compile_matcher()),
+            RuleKind::Regex => Matcher::Regex(
+                RegexBuilder::new(&rule.pattern)
+                    .case_insensitive(rule.case_insensitive)
+                    .build()
+                    .map_err(|e| e.to_string())?,
+            ),
+        })
+    }
+
+    fn is_match(&self, path: &Path, relative: &str) -> bool {
+        match self {
+            Matcher::Folder { name, case_insensitive } => path
+                .parent()
+                .into_iter()
+                .flat_map(|p| p.components())
+                .filter_map(|c| match c {
+                    Component::Normal(os) => os.to_str(),
+                    _ => None,
+                })
+                .any(|c| if *case_insensitive { c.to_lowercase() == name.to_lowercase() } else { c == name }),
+            Matcher::Glob(glob) => glob.is_match(relative),
+            Matcher::Filename(glob) => path.file_name().is_some_and(|n| glob.is_match(n)),
+            Matcher::Regex(regex) => regex.is_match(relative),
         }
     }
+}
 
-    let mut tags = Vec::new();
-    for rule in rules {
-        if components.iter().any(|c| c == &rule.folder) {
-            for t in rule.tags.split_whitespace() {
-                if !t.is_empty() && !tags.contains(&t.to_string()) {
-                    tags.push(t.to_string());
+/// Checks that the rule's pattern compiles.
+pub fn validate(rule: &AutoTagRule) -> Result<(), String> {
+    Matcher::new(rule).map(|_| ())
+}
+
+/// A target's auto-tag rules, compiled once. Rules that fail to compile are
+/// left out, settings are validated when saved so this should not happen.
+pub struct TagRules {
+    rules: Vec<(Matcher, Vec<String>)>,
+}
+
+impl TagRules {
+    pub fn new(rules: &[AutoTagRule]) -> Self {
+        let rules = rules
+            .iter()
+            .filter_map(|rule| match Matcher::new(rule) {
+                Ok(m) => Some((m, rule.tags.split_whitespace().map(String::from).collect())),
+                Err(e) => {
+                    warn!("Skipping auto-tag rule '{}': {}", rule.pattern, e);
+                    None
+                }
+            })
+            .collect();
+        TagRules { rules }
+    }
+
+    /// Tags from every rule matching `path`. Globs and regexes see the path
+    /// relative to `root`, the watched or uploaded folder, with `/` separators.
+    pub fn tags_for_path(&self, path: &Path, root: &Path) -> Vec<String> {
+        let relative = path
+            .strip_prefix(root)
+            .unwrap_or(path)
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(os) => Some(os.to_string_lossy()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+
+        let mut tags: Vec<String> = Vec::new();
+        for (matcher, rule_tags) in &self.rules {
+            if matcher.is_match(path, &relative) {
+                for t in rule_tags {
+                    if !tags.contains(t) {
+                        tags.push(t.clone());
+                    }
                 }
             }
         }
+        tags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn rule(kind: RuleKind, pattern: &str, tags: &str, case_insensitive: bool) -> AutoTagRule {
+        AutoTagRule {
+            kind,
+            pattern: pattern.into(),
+            tags: tags.into(),
+            case_insensitive,
+        }
+    }
+
+    fn tags(rules: &[AutoTagRule], path: &str) -> Vec<String> {
+        let root = PathBuf::from("/w");
+        TagRules::new(rules).tags_for_path(&root.join(path), &root)
+    }
+
+    #[test]
+    fn folder_rule_matches_whole_parent_names() {
+        let rules = [rule(RuleKind::Folder, "cats", "cat", false)];
+        assert_eq!(tags(&rules, "cats/a.png"), ["cat"]);
+        assert_eq!(tags(&rules, "x/cats/y/a.png"), ["cat"]);
+        assert!(tags(&rules, "cats2/a.png").is_empty());
+        assert!(tags(&rules, "Cats/a.png").is_empty());
+        // The file name is not a folder
+        assert!(tags(&rules, "cats").is_empty());
+    }
+
+    #[test]
+    fn folder_rule_ignoring_case() {
+        let rules = [rule(RuleKind::Folder, "cats", "cat", true)];
+        assert_eq!(tags(&rules, "CATS/a.png"), ["cat"]);
+    }
+
+    #[test]
+    fn legacy_folder_rules_still_load() {
+        let rule: AutoTagRule = serde_json::from_str(r#"{"folder": "cats", "tags": "cat"}"#).unwrap();
+        assert_eq!(rule.kind, RuleKind::Folder);
+        assert_eq!(tags(&[rule], "cats/a.png"), ["cat"]);
+    }
+
+    #[test]
+    fn glob_rule_matches_relative_path() {
+        let rules = [
+            rule(RuleKind::Glob, "**/Screenshots/**", "screenshot", false),
+            rule(RuleKind::Glob, "*.png", "top", false),
+        ];
+        assert_eq!(tags(&rules, "Screenshots/a.png"), ["screenshot"]);
+        assert_eq!(tags(&rules, "a/Screenshots/b/a.png"), ["screenshot"]);
+        assert_eq!(tags(&rules, "a.png"), ["top"]);
+        // `*` does not cross folders
+        assert!(tags(&rules, "a/b.png").is_empty());
+        assert!(tags(&rules, "a/screenshots/b.jpg").is_empty());
+    }
+
+    #[test]
+    fn anchored_patterns_see_paths_inside_root() {
+        let rules = [
+            rule(RuleKind::Glob, "Screenshots/**", "screenshot", false),
+            rule(RuleKind::Regex, "^2024/", "2024", false),
+        ];
+        assert_eq!(tags(&rules, "Screenshots/a.png"), ["screenshot"]);
+        assert_eq!(tags(&rules, "2024/a.png"), ["2024"]);
+        assert!(tags(&rules, "old/2024/a.png").is_empty());
+    }
+
+    #[test]
+    fn filename_rule_ignores_folders() {
+        let rules = [rule(RuleKind::Filename, "*.gif", "gif", true)];
+        assert_eq!(tags(&rules, "a/b/c.GIF"), ["gif"]);
+        assert!(tags(&rules, "x.gif/c.png").is_empty());
+    }
+
+    #[test]
+    fn regex_rule_case() {
+        let sensitive = [rule(RuleKind::Regex, r"img_\d+", "camera", false)];
+        assert_eq!(tags(&sensitive, "a/img_0001.jpg"), ["camera"]);
+        assert!(tags(&sensitive, "a/IMG_0001.jpg").is_empty());
+        let insensitive = [rule(RuleKind::Regex, r"img_\d+", "camera", true)];
+        assert_eq!(tags(&insensitive, "a/IMG_0001.jpg"), ["camera"]);
+    }
+
+    #[test]
+    fn tags_are_deduplicated_in_order() {
+        let rules = [
+            rule(RuleKind::Folder, "cats", "cat pet", false),
+            rule(RuleKind::Filename, "*.png", "pet  image", false),
+        ];
+        assert_eq!(tags(&rules, "cats/a.png"), ["cat", "pet", "image"]);
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected_and_skipped() {
+        let bad = rule(RuleKind::Regex, "(", "x", false);
+        assert!(validate(&bad).is_err());
+        assert!(validate(&rule(RuleKind::Glob, "a[", "x", false)).is_err());
+        assert!(validate(&rule(RuleKind::Glob, "**/*.png", "x", false)).is_ok());
+
+        let rules = [bad, rule(RuleKind::Filename, "*.png", "png", false)];
+        assert_eq!(tags(&rules, "a.png"), ["png"]);
+    }
+
+    #[test]
+    fn paths_outside_root_use_the_whole_path() {
+        let rules = [rule(RuleKind::Glob, "elsewhere/**", "x", false)];
+        let tags = TagRules::new(&rules).tags_for_path(Path::new("/elsewhere/a.png"), Path::new("/w"));
+        assert_eq!(tags, ["x"]);
     }
-    tags
 }
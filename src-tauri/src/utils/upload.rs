@@ -0,0 +1,102 @@
+use std::path::Path;
+use tracing::{info, info_span, warn};
+
+use crate::utils::{
+    self,
+    erabooru::Error,
+    events::{self, EventSink, Status},
+    hash_index::HashIndex,
+    queue::{JobState, Queue, QueueSource},
+};
+
+// While listing a folder, the job is checked for cancellation once per this many files
+const CANCEL_CHECK_INTERVAL: usize = 100;
+
+/// Counts the videos and images under `folder` and their total size in bytes.
+pub fn scan_folder(folder: &Path) -> Result<(u64, u64, u64), Error> {
+    let mut videos = 0u64;
+    let mut images = 0u64;
+    let mut size = 0u64;
+    for path in utils::files::media_files(folder, true) {
+        let metadata = std::fs::metadata(&path).map_err(Error::io)?;
+        size += metadata.len();
+        let guess = mime_guess::MimeGuess::from_path(&path).first();
+        if let Some(m) = guess {
+            if m.type_() == mime_guess::mime::IMAGE {
+                images += 1;
+            } else if m.type_() == mime_guess::mime::VIDEO {
+                videos += 1;
+            }
+        }
+    }
+    Ok((videos, images, size))
+}
+
+/// Checks that `folder` is an existing folder and returns its absolute path,
+/// the form a job stores so auto-tag rules see paths inside it.
+pub fn resolve_folder(folder: &Path) -> Result<String, Error> {
+    let resolved = std::fs::canonicalize(folder)
+        .map_err(|e| Error::config(format!("{}: {}", folder.display(), e)))?;
+    if !resolved.is_dir() {
+        return Err(Error::config(format!("not a folder: {}", folder.display())));
+    }
+    Ok(resolved.to_string_lossy().into_owned())
+}
+
+/// Queues every media file under `folder` for upload as part of `job`,
+/// skipping files uploaded before. Stops early if the job is cancelled.
+/// Returns the number of files queued.
+pub fn list_folder(
+    events: &dyn EventSink,
+    queue: &Queue,
+    hashes: &HashIndex,
+    job: i64,
+    folder: &str,
+    target: &str,
+) -> u64 {
+    let _span = info_span!("job", id = job).entered();
+    let mut queued = 0u64;
+    let mut skipped = 0u64;
+    for (i, path) in utils::files::media_files(Path::new(folder), true).enumerate() {
+        if i % CANCEL_CHECK_INTERVAL == 0 {
+            flush_skipped(queue, job, &mut skipped);
+            if matches!(queue.job_state(job), Ok(Some(JobState::Cancelled))) {
+                info!("Upload job {} cancelled while listing {}", job, folder);
+                break;
+            }
+        }
+        if hashes.is_uploaded(&path, target) {
+            skipped += 1;
+            continue;
+        }
+        match queue.enqueue(&path, QueueSource::Upload, target, Some(job)) {
+            Ok(true) => {
+                queued += 1;
+                events::emit(events, &path, target, QueueSource::Upload, Status::Detected);
+            }
+            Ok(false) => {}
+            Err(e) => warn!("Failed to queue {}: {}", path.display(), e),
+        }
+    }
+    // Files queued between the cancellation and noticing it
+    if matches!(queue.job_state(job), Ok(Some(JobState::Cancelled))) {
+        if let Err(e) = queue.cancel_leftovers(job) {
+            warn!("Failed to cancel files of upload job {}: {}", job, e);
+        }
+    }
+    flush_skipped(queue, job, &mut skipped);
+    if let Err(e) = queue.finish_listing(job) {
+        warn!("Failed to update upload job {}: {}", job, e);
+    }
+    info!("Queued {} files from {}", queued, folder);
+    queued
+}
+
+fn flush_skipped(queue: &Queue, job: i64, skipped: &mut u64) {
+    if *skipped > 0 {
+        if let Err(e) = queue.add_skipped(job, *skipped) {
+            warn!("Failed to update upload job {}: {}", job, e);
+        }
+        *skipped = 0;
+    }
+}
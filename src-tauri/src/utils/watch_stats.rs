@@ -0,0 +1,57 @@
+use serde::Serialize;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Counters {
+    pub started_at: Option<String>,
+    /// Files the watchers queued for upload
+    pub files_seen: u64,
+    pub uploaded: u64,
+    pub duplicates: u64,
+    pub failures: u64,
+    pub last_error: Option<String>,
+}
+
+/// Counters for the current watch session, updated by the watchers and by
+/// the upload workers for files that came from a watcher.
+#[derive(Default)]
+pub struct WatchStats {
+    counters: Mutex<Counters>,
+}
+
+impl WatchStats {
+    /// Starts a new session, dropping the counts of the previous one.
+    pub fn reset(&self) {
+        *self.counters.lock().unwrap() = Counters {
+            started_at: Some(chrono::Utc::now().to_rfc3339()),
+            ..Counters::default()
+        };
+    }
+
+    pub fn seen(&self) {
+        self.counters.lock().unwrap().files_seen += 1;
+    }
+
+    pub fn uploaded(&self) {
+        self.counters.lock().unwrap().uploaded += 1;
+    }
+
+    pub fn duplicate(&self) {
+        self.counters.lock().unwrap().duplicates += 1;
+    }
+
+    pub fn failed(&self, error: &str) {
+        let mut counters = self.counters.lock().unwrap();
+        counters.failures += 1;
+        counters.last_error = Some(error.to_string());
+    }
+
+    /// Records a problem that is not tied to a file, e.g. a broken watch.
+    pub fn error(&self, error: &str) {
+        self.counters.lock().unwrap().last_error = Some(error.to_string());
+    }
+
+    pub fn snapshot(&self) -> Counters {
+        self.counters.lock().unwrap().clone()
+    }
+}
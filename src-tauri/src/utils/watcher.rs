@@ -0,0 +1,352 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tracing::{info, info_span, warn};
+use notify::{
+    event::{ModifyKind, RemoveKind, RenameMode},
+    recommended_watcher, Config, Event, EventKind, PollWatcher, RecursiveMode, Watcher,
+};
+
+use crate::utils::{
+    self,
+    erabooru::Error,
+    events::{self, EventSink, Status, WatcherState},
+    hash_index::HashIndex,
+    queue::{Queue, QueueSource},
+    settle::Settler,
+    store::{WatchMode, WatchTarget},
+    watch_stats::WatchStats,
+};
+
+// Delay before re-arming a broken watch, doubled on every failed attempt
+const RESTART_MIN_DELAY: Duration = Duration::from_secs(1);
+const RESTART_MAX_DELAY: Duration = Duration::from_secs(60);
+// How often the watched folder is checked to still exist
+const FOLDER_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+// The two names of a rename arrive as separate events on most platforms,
+// they are paired when they come this close together
+const RENAME_PAIR_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Everything a watcher needs, shared by all watchers.
+pub struct Context {
+    pub events: Arc<dyn EventSink>,
+    pub queue: Arc<Queue>,
+    pub hashes: Arc<HashIndex>,
+    pub stats: Arc<WatchStats>,
+}
+
+pub struct WatcherHandle {
+    stop: Sender<()>,
+    handle: JoinHandle<()>,
+    folder: String,
+    state: Arc<Mutex<WatcherState>>,
+}
+
+impl WatcherHandle {
+    pub fn folder(&self) -> &str {
+        &self.folder
+    }
+
+    pub fn state(&self) -> WatcherState {
+        self.state.lock().unwrap().clone()
+    }
+
+    /// Stops watching and waits for the thread to finish.
+    pub fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.handle.join();
+    }
+}
+
+/// Starts watching the target's folder on a thread of its own. Setup
+/// problems are returned, later ones are retried until the watcher is stopped.
+pub fn spawn(ctx: Arc<Context>, target: WatchTarget) -> Result<WatcherHandle, Error> {
+    if target.folder.is_empty() || target.server.is_empty() {
+        return Err(Error::config(format!("folder or server not set for target {}", target.id)));
+    }
+    let armed = arm(&target).map_err(Error::io)?;
+
+    let (stop_tx, stop_rx) = channel::<()>();
+    let state = Arc::new(Mutex::new(WatcherState::Running));
+    let folder = target.folder.clone();
+
+    //todo: lock settings changes while watching
+    info!(
+        "Starting watcher for folder: {} (target: {}, recursive: {}, mode: {:?})",
+        target.folder, target.id, target.recursive, target.watch_mode
+    );
+    let supervisor = Supervisor {
+        ctx,
+        target,
+        state: state.clone(),
+        stop_rx,
+    };
+    let handle = std::thread::spawn(move || supervisor.run(armed));
+    Ok(WatcherHandle {
+        stop: stop_tx,
+        handle,
+        folder,
+        state,
+    })
+}
+
+type Armed = (Box<dyn Watcher + Send>, Receiver<notify::Result<Event>>);
+
+/// Creates a watcher for the target folder and starts watching it.
+fn arm(target: &WatchTarget) -> Result<Armed, String> {
+    let folder = Path::new(&target.folder);
+    if !folder.is_dir() {
+        return Err(format!("folder not found: {}", target.folder));
+    }
+    let (tx, rx) = channel();
+    let handler = move |res| {
+        tx.send(res).ok();
+    };
+    let mut watcher: Box<dyn Watcher + Send> = match target.watch_mode {
+        WatchMode::Native => Box::new(recommended_watcher(handler).map_err(|e| e.to_string())?),
+        WatchMode::Poll => {
+            let config = Config::default().with_poll_interval(Duration::from_secs(target.poll_interval_secs));
+            Box::new(PollWatcher::new(handler, config).map_err(|e| e.to_string())?)
+        }
+    };
+    let mode = if target.recursive {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+    watcher.watch(folder, mode).map_err(|e| e.to_string())?;
+    Ok((watcher, rx))
+}
+
+/// Errors after which the watch no longer covers the folder
+fn is_fatal(e: &notify::Error) -> bool {
+    match &e.kind {
+        notify::ErrorKind::MaxFilesWatch | notify::ErrorKind::WatchNotFound => true,
+        // ENOSPC is how inotify reports running out of watches
+        notify::ErrorKind::Io(io) => io.raw_os_error() == Some(28),
+        _ => false,
+    }
+}
+
+/// Owns a watcher thread. When the watch breaks, e.g. the folder is deleted,
+/// unmounted or inotify runs out of watches, it is re-armed with backoff
+/// until the folder is back.
+struct Supervisor {
+    ctx: Arc<Context>,
+    target: WatchTarget,
+    state: Arc<Mutex<WatcherState>>,
+    stop_rx: Receiver<()>,
+}
+
+impl Supervisor {
+    fn run(self, armed: Armed) {
+        let _span = info_span!("watcher", target = %self.target.id).entered();
+        let mut armed = Some(armed);
+        let mut delay = RESTART_MIN_DELAY;
+        loop {
+            let error = match armed.take().map_or_else(|| arm(&self.target), Ok) {
+                Ok((watcher, events)) => {
+                    self.set_state(WatcherState::Running);
+                    let result = self.watch(&events);
+                    drop(watcher);
+                    match result {
+                        Ok(()) => return,
+                        Err(e) => {
+                            delay = RESTART_MIN_DELAY;
+                            e
+                        }
+                    }
+                }
+                Err(e) => e,
+            };
+            warn!(
+                "Watcher for {} failed: {}, retrying in {}s",
+                self.target.folder,
+                error,
+                delay.as_secs()
+            );
+            self.set_state(WatcherState::Failed {
+                error,
+                retry_in_ms: delay.as_millis() as u64,
+            });
+            if !matches!(self.stop_rx.recv_timeout(delay), Err(RecvTimeoutError::Timeout)) {
+                return;
+            }
+            delay = (delay * 2).min(RESTART_MAX_DELAY);
+        }
+    }
+
+    /// Handles the new name of a rename, or a path moved in from outside when
+    /// no old name is waiting to be paired with it.
+    fn arrived(&self, settler: &mut Settler, renamed_from: &mut Option<(PathBuf, Instant)>, path: PathBuf) {
+        match renamed_from.take() {
+            Some((from, at)) if at.elapsed() < RENAME_PAIR_TIMEOUT => self.renamed(settler, &from, path),
+            _ => track_new_path(settler, path, self.target.recursive),
+        }
+    }
+
+    /// Follows a file or folder renamed inside the watched folder. Files
+    /// the hash index knows were uploaded under the old name are not queued
+    /// again, whatever else arrives with the new name is.
+    fn renamed(&self, settler: &mut Settler, from: &Path, to: PathBuf) {
+        let target = &self.target.id;
+        if let Err(e) = self.ctx.queue.rename(from, &to, target) {
+            warn!("Failed to update queue for rename of {}: {}", from.display(), e);
+        }
+        if let Err(e) = self.ctx.hashes.rename(from, &to, target) {
+            warn!("Failed to update hash index for rename of {}: {}", from.display(), e);
+        }
+        let files: Vec<PathBuf> = if to.is_dir() {
+            if !self.target.recursive {
+                return;
+            }
+            utils::files::media_files(&to, true).collect()
+        } else if utils::files::is_media_file(&to) {
+            vec![to]
+        } else {
+            return;
+        };
+        for file in files {
+            if !self.ctx.hashes.is_uploaded(&file, target) {
+                settler.touch(file);
+            }
+        }
+    }
+
+    fn set_state(&self, state: WatcherState) {
+        if let WatcherState::Failed { error, .. } = &state {
+            self.ctx.stats.error(error);
+        }
+        *self.state.lock().unwrap() = state.clone();
+        events::emit_watcher(&*self.ctx.events, &self.target.id, state);
+    }
+
+    /// Handles events until stopped, or returns why the watch broke.
+    fn watch(&self, events: &Receiver<notify::Result<Event>>) -> Result<(), String> {
+        let recursive = self.target.recursive;
+        // Live events wait in the channel until the scan is done. It runs
+        // again after a re-arm to pick up what arrived in the meantime.
+        let mut settler = Settler::new(Duration::from_millis(self.target.settle_ms));
+        if self.target.catch_up && !catch_up(&self.ctx, &self.target, &self.stop_rx, &mut settler) {
+            return Ok(());
+        }
+        let mut last_check = Instant::now();
+        // The old name of a file until its new name arrives
+        let mut renamed_from: Option<(PathBuf, Instant)> = None;
+        loop {
+            if self.stop_rx.try_recv().is_ok() {
+                return Ok(());
+            }
+            if last_check.elapsed() >= FOLDER_CHECK_INTERVAL {
+                if !Path::new(&self.target.folder).is_dir() {
+                    return Err("folder is no longer available".to_string());
+                }
+                last_check = Instant::now();
+            }
+            let event = match events.recv_timeout(Duration::from_millis(500)) {
+                Ok(Ok(event)) => Some(event),
+                Ok(Err(e)) if is_fatal(&e) => return Err(e.to_string()),
+                Ok(Err(e)) => {
+                    warn!("Watcher error for {}: {}", self.target.folder, e);
+                    None
+                }
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => return Err("watcher stopped unexpectedly".to_string()),
+            };
+            if let Some(event) = event {
+                match event.kind {
+                    EventKind::Create(_) => {
+                        for path in event.paths {
+                            track_new_path(&mut settler, path, recursive);
+                        }
+                    }
+                    // The new name, or moved into the folder from outside
+                    EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                        for path in event.paths {
+                            self.arrived(&mut settler, &mut renamed_from, path);
+                        }
+                    }
+                    // The old name, or moved out of the folder
+                    EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                        for path in event.paths {
+                            settler.forget(&path);
+                            renamed_from = Some((path, Instant::now()));
+                        }
+                    }
+                    // Sent by inotify after the From and To events of the same rename
+                    EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {}
+                    // The platform could not tell which side of the rename this
+                    // is (macOS), so it is told by whether the path still exists
+                    EventKind::Modify(ModifyKind::Name(_)) => {
+                        for path in event.paths {
+                            if path.exists() {
+                                self.arrived(&mut settler, &mut renamed_from, path);
+                            } else {
+                                settler.forget(&path);
+                                renamed_from = Some((path, Instant::now()));
+                            }
+                        }
+                    }
+                    EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Metadata(_) | ModifyKind::Any) => {
+                        for path in event.paths {
+                            settler.touch_if_pending(path);
+                        }
+                    }
+                    EventKind::Remove(RemoveKind::File | RemoveKind::Any) => {
+                        for path in &event.paths {
+                            settler.forget(path);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            for path in settler.take_settled() {
+                enqueue(&self.ctx, &path, &self.target.id);
+            }
+        }
+    }
+}
+
+fn track_new_path(settler: &mut Settler, path: PathBuf, recursive: bool) {
+    if recursive && path.is_dir() {
+        // A new sub-folder may already hold files by the time
+        // its watch is registered, e.g. when it was moved in.
+        for file in utils::files::media_files(&path, true) {
+            settler.touch(file);
+        }
+    } else if utils::files::is_media_file(&path) {
+        settler.touch(path);
+    }
+}
+
+/// Hands files that appeared while the folder was not watched to the settler,
+/// skipping those the hash index knows were uploaded and are unchanged. Some
+/// may still be being copied. Returns `false` if stopped midway.
+fn catch_up(ctx: &Context, target: &WatchTarget, stop_rx: &Receiver<()>, settler: &mut Settler) -> bool {
+    let mut found = 0u64;
+    for path in utils::files::media_files(Path::new(&target.folder), target.recursive) {
+        if stop_rx.try_recv().is_ok() {
+            return false;
+        }
+        if !ctx.hashes.is_uploaded(&path, &target.id) {
+            settler.touch(path);
+            found += 1;
+        }
+    }
+    info!("Catch-up scan of {} found {} new files", target.folder, found);
+    true
+}
+
+fn enqueue(ctx: &Context, path: &Path, target: &str) {
+    match ctx.queue.enqueue(path, QueueSource::Watch, target, None) {
+        Ok(true) => {
+            info!("Queued: {}", path.display());
+            ctx.stats.seen();
+            events::emit(&*ctx.events, path, target, QueueSource::Watch, Status::Detected);
+        }
+        Ok(false) => {}
+        Err(e) => warn!("Failed to queue {}: {}", path.display(), e),
+    }
+}
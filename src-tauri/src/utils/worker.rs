@@ -0,0 +1,266 @@
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, info_span, warn};
+
+use crate::utils::{
+    self,
+    erabooru::{ClientCache, Error, UploadResult},
+    events::{self, EventSink, Status},
+    hash_index::{Fingerprint, HashIndex, Outcome},
+    history::{History, HistoryStatus},
+    queue::{Queue, QueueItem, QueueSource, QueueState},
+    store::SettingsSource,
+    watch_stats::WatchStats,
+};
+
+// Media is checked for indexing this many times, this far apart, before tagging gives up
+const MAX_INDEX_CHECKS: u32 = 10;
+const INDEX_CHECK_DELAY: Duration = Duration::from_millis(500);
+
+/// Everything a worker needs, shared by all workers of a pool.
+pub struct Context {
+    pub events: Arc<dyn EventSink>,
+    pub settings: Arc<dyn SettingsSource>,
+    pub queue: Arc<Queue>,
+    pub clients: Arc<ClientCache>,
+    pub hashes: Arc<HashIndex>,
+    pub history: Arc<History>,
+    pub stats: Arc<WatchStats>,
+}
+
+/// A resizable set of worker threads draining the upload queue in parallel.
+/// Workers are never joined: whatever they are doing when the app quits is
+/// picked up again from the queue on the next launch.
+pub struct WorkerPool {
+    ctx: Arc<Context>,
+    stops: Vec<Sender<()>>,
+}
+
+impl WorkerPool {
+    pub fn new(ctx: Context) -> Self {
+        WorkerPool {
+            ctx: Arc::new(ctx),
+            stops: Vec::new(),
+        }
+    }
+
+    /// Starts or stops workers until exactly `size` are running. Workers being
+    /// stopped finish the item they are on first.
+    pub fn resize(&mut self, size: usize) {
+        while self.stops.len() < size {
+            let (stop_tx, stop_rx) = channel::<()>();
+            let ctx = self.ctx.clone();
+            std::thread::spawn(move || run(&ctx, stop_rx));
+            self.stops.push(stop_tx);
+        }
+        while self.stops.len() > size {
+            if let Some(stop) = self.stops.pop() {
+                let _ = stop.send(());
+            }
+        }
+    }
+}
+
+/// Drains the upload queue until a stop signal arrives. Settings are re-read
+/// for every item so changes made while the worker runs are picked up.
+fn run(ctx: &Context, stop_rx: Receiver<()>) {
+    loop {
+        if stop_rx.try_recv().is_ok() {
+            break;
+        }
+        match ctx.queue.claim_next() {
+            Ok(Some(item)) => process_item(ctx, item),
+            Ok(None) => {
+                // Nothing to do, wait for new items or a stop signal
+                if stop_rx.recv_timeout(Duration::from_millis(500)).is_ok() {
+                    break;
+                }
+            }
+            Err(e) => {
+                error!("Failed to read upload queue: {}", e);
+                if stop_rx.recv_timeout(Duration::from_secs(5)).is_ok() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn process_item(ctx: &Context, item: QueueItem) {
+    let _span = info_span!("upload", id = item.id, target = %item.target, path = %item.path.display()).entered();
+    let settings = match ctx.settings.settings() {
+        Ok(s) => s,
+        Err(e) => {
+            fail(ctx, &item, &Error::config(format!("failed to load settings: {}", e)));
+            return;
+        }
+    };
+    let Some(target) = settings.target(&item.target) else {
+        fail(ctx, &item, &Error::config(format!("watch target {} no longer exists", item.target)));
+        return;
+    };
+    let client = match ctx.clients.get(&settings, target) {
+        Ok(c) => c,
+        Err(e) => {
+            fail(ctx, &item, &e);
+            return;
+        }
+    };
+
+    let path = item.path.as_path();
+    let media_id = match (item.state, item.media_id.clone()) {
+        (QueueState::Tagging, Some(id)) => id,
+        _ => {
+            let file = match utils::files::retry_open_file(path, 3, Duration::from_millis(1000)) {
+                Ok(f) => f,
+                Err(e) => {
+                    fail(ctx, &item, &Error::io(e));
+                    return;
+                }
+            };
+            let content_type = utils::files::get_file_mime_type(path)
+                .unwrap_or_else(|| "application/octet-stream".into());
+
+            let (hash, fingerprint) = match content_hash(ctx, &item, &file) {
+                Ok(h) => h,
+                Err(e) => {
+                    fail(ctx, &item, &e);
+                    return;
+                }
+            };
+            let on_sent = {
+                let (events, item) = (ctx.events.clone(), item.clone());
+                move |sent, total| emit(&*events, &item, Status::Uploading { sent, total })
+            };
+            let (id, outcome) = match client.upload_media(file, &hash, &content_type, on_sent) {
+                Ok(UploadResult::Uploaded(id)) => {
+                    info!("Uploaded as {}", id);
+                    emit(&*ctx.events, &item, Status::Uploaded { media_id: id.clone() });
+                    record(ctx, &item, HistoryStatus::Uploaded, Some(&id), &[], None);
+                    if item.source == QueueSource::Watch {
+                        ctx.stats.uploaded();
+                    }
+                    (id, Outcome::Uploaded)
+                }
+                Ok(UploadResult::Duplicate(id)) => {
+                    info!("Skipped, already on the server as {}", id);
+                    emit(&*ctx.events, &item, Status::Duplicate { media_id: id.clone() });
+                    record(ctx, &item, HistoryStatus::Duplicate, Some(&id), &[], None);
+                    if item.source == QueueSource::Watch {
+                        ctx.stats.duplicate();
+                    }
+                    (id, Outcome::Duplicate)
+                }
+                Err(e) => {
+                    fail(ctx, &item, &e);
+                    return;
+                }
+            };
+            if let Err(e) = ctx.hashes.record(path, &item.target, fingerprint, &hash, Some(outcome)) {
+                warn!("Failed to update hash index: {}", e);
+            }
+            // Tagging waits for the server to index the media. That wait is a
+            // separate queue step so this worker can move on to other uploads.
+            if let Err(e) = ctx.queue.mark_tagging(item.id, &id, outcome == Outcome::Duplicate) {
+                warn!("Failed to update queue item {}: {}", item.id, e);
+            }
+            return;
+        }
+    };
+
+    match client.check_media_exists(&media_id) {
+        Ok(true) => {}
+        result => {
+            if let Err(e) = result {
+                warn!("Error checking media existence: {}", e);
+            }
+            if item.index_checks + 1 < MAX_INDEX_CHECKS {
+                if let Err(e) = ctx.queue.defer_tagging(item.id, INDEX_CHECK_DELAY) {
+                    warn!("Failed to update queue item {}: {}", item.id, e);
+                }
+            } else {
+                let error = Error::Protocol {
+                    message: format!("media {} was not indexed after {} attempts", media_id, MAX_INDEX_CHECKS),
+                };
+                fail(ctx, &item, &error);
+            }
+            return;
+        }
+    }
+
+    // Rules match paths inside the uploaded folder for bulk uploads
+    let root = match item.job.map(|job| ctx.queue.job_folder(job)) {
+        Some(Ok(Some(folder))) => PathBuf::from(folder),
+        Some(Err(e)) => {
+            warn!("Failed to read upload job {:?}: {}", item.job, e);
+            PathBuf::from(&target.folder)
+        }
+        _ => PathBuf::from(&target.folder),
+    };
+    let tags = ctx.clients.tag_rules(target).tags_for_path(path, &root);
+    // The item fails with its media id kept, so a retry only tags it again
+    match client.apply_tags_and_date(path, &tags, &media_id, target.override_upload_date) {
+        Ok(()) => {
+            info!("Tagged {} with {:?}", media_id, tags);
+            record(ctx, &item, HistoryStatus::Tagged, Some(&media_id), &tags, None);
+        }
+        Err(e) => {
+            fail(ctx, &item, &e);
+            return;
+        }
+    }
+    emit(&*ctx.events, &item, Status::Tagged { media_id });
+    if let Err(e) = ctx.queue.mark_done(item.id) {
+        warn!("Failed to update queue item {}: {}", item.id, e);
+    }
+}
+
+/// Hashes the file unless the hash index already knows it unchanged.
+fn content_hash(ctx: &Context, item: &QueueItem, file: &File) -> Result<(String, Fingerprint), Error> {
+    let fingerprint = Fingerprint::of(&file.metadata().map_err(Error::io)?);
+    match ctx.hashes.lookup(&item.path, &item.target, fingerprint) {
+        Ok(Some(entry)) => return Ok((entry.hash, fingerprint)),
+        Ok(None) => {}
+        Err(e) => warn!("Failed to read hash index: {}", e),
+    }
+
+    debug!("Hashing");
+    emit(&*ctx.events, item, Status::Hashing);
+    let hash = utils::files::hash_file(&mut &*file).map_err(Error::io)?;
+    if let Err(e) = ctx.hashes.record(&item.path, &item.target, fingerprint, &hash, None) {
+        warn!("Failed to update hash index: {}", e);
+    }
+    Ok((hash, fingerprint))
+}
+
+fn emit(sink: &dyn EventSink, item: &QueueItem, status: Status) {
+    events::emit(sink, &item.path, &item.target, item.source, status);
+}
+
+fn record(
+    ctx: &Context,
+    item: &QueueItem,
+    status: HistoryStatus,
+    media_id: Option<&str>,
+    tags: &[String],
+    error: Option<&Error>,
+) {
+    if let Err(e) = ctx.history.record(item, status, media_id, tags, error) {
+        warn!("Failed to write upload history: {}", e);
+    }
+}
+
+fn fail(ctx: &Context, item: &QueueItem, error: &Error) {
+    error!("Upload failed: {}", error);
+    emit(&*ctx.events, item, Status::Failed { error: error.clone() });
+    record(ctx, item, HistoryStatus::Failed, item.media_id.as_deref(), &[], Some(error));
+    if item.source == QueueSource::Watch {
+        ctx.stats.failed(&error.to_string());
+    }
+    if let Err(e) = ctx.queue.mark_failed(item.id, error) {
+        warn!("Failed to update queue item {}: {}", item.id, e);
+    }
+}
@@ -0,0 +1,162 @@
+import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
+
+export type QueueSource = 'watch' | 'upload';
+
+export interface UploadError {
+  kind: string;
+  message?: string;
+  status?: number;
+  body?: string;
+}
+
+export type Status =
+  | { status: 'detected' }
+  | { status: 'hashing' }
+  | { status: 'uploading'; sent: number; total: number }
+  | { status: 'uploaded'; media_id: string }
+  | { status: 'duplicate'; media_id: string }
+  | { status: 'tagged'; media_id: string }
+  | { status: 'failed'; error: UploadError };
+
+export type Progress = {
+  path: string;
+  target: string;
+  source: QueueSource;
+} & Status;
+
+export interface QueueItem {
+  id: number;
+  path: string;
+  source: QueueSource;
+  target: string;
+  state: 'pending' | 'uploading' | 'tagging' | 'done' | 'failed' | 'cancelled';
+  job: number | null;
+  media_id: string | null;
+  duplicate: boolean;
+  error: UploadError | null;
+  attempts: number;
+  index_checks: number;
+  created_at: string;
+  updated_at: string;
+}
+
+export interface Job {
+  id: number;
+  folder: string;
+  target: string;
+  state: 'running' | 'paused' | 'cancelled';
+  listing: boolean;
+  uploaded: number;
+  duplicate: number;
+  failed: number;
+  cancelled: number;
+  remaining: number;
+  skipped: number;
+  created_at: string;
+}
+
+export function isFinished(job: Job) {
+  return !job.listing && job.remaining === 0;
+}
+
+export function queueSnapshot() {
+  return invoke<QueueItem[]>('queue_snapshot');
+}
+
+export function failedUploads() {
+  return invoke<QueueItem[]>('failed_uploads');
+}
+
+/** Queues failed uploads again, all of them when `ids` is left out. */
+export function retryFailed(ids?: number[]) {
+  return invoke<number>('retry_failed', { ids: ids ?? null });
+}
+
+export function listenProgress(handler: (progress: Progress) => void) {
+  return listen<Progress>('upload-progress', (event) => handler(event.payload));
+}
+
+export type WatcherStatus = { target: string } & (
+  | { state: 'running' }
+  | { state: 'failed'; error: string; retry_in_ms: number }
+);
+
+export interface WatchStatus {
+  state: 'running' | 'stopped' | 'errored';
+  folders: ({ folder: string } & WatcherStatus)[];
+  started_at: string | null;
+  files_seen: number;
+  uploaded: number;
+  duplicates: number;
+  failures: number;
+  last_error: string | null;
+}
+
+export function watcherStatus() {
+  return invoke<WatchStatus>('watcher_status');
+}
+
+export function listenWatcher(handler: (status: WatcherStatus) => void) {
+  return listen<WatcherStatus>('watcher-status', (event) => handler(event.payload));
+}
+
+/** Turns a queue item into the progress it last reported, `null` for cancelled items. */
+export function itemProgress(item: QueueItem): Progress | null {
+  const base = { path: item.path, target: item.target, source: item.source };
+  switch (item.state) {
+    case 'pending':
+      return { ...base, status: 'detected' };
+    case 'uploading':
+      return { ...base, status: 'hashing' };
+    case 'tagging':
+      return { ...base, status: 'uploaded', media_id: item.media_id ?? '' };
+    case 'done':
+      return { ...base, status: 'tagged', media_id: item.media_id ?? '' };
+    case 'cancelled':
+      return null;
+    case 'failed':
+      return { ...base, status: 'failed', error: item.error ?? { kind: 'other', message: 'unknown error' } };
+  }
+}
+
+export function describeError(error: UploadError) {
+  if (error.message) {
+    return error.message;
+  }
+  return error.body ? `status ${error.status}: ${error.body}` : `status ${error.status}`;
+}
+
+// Commands fail with either a plain message or an `UploadError`
+export function describeCommandError(e: unknown) {
+  return typeof e === 'string' ? e : describeError(e as UploadError);
+}
+
+export interface HistoryEntry {
+  id: number;
+  time: string;
+  path: string;
+  target: string;
+  source: QueueSource;
+  status: 'uploaded' | 'duplicate' | 'tagged' | 'failed';
+  media_id: string | null;
+  tags: string[];
+  error: UploadError | null;
+}
+
+export interface HistoryQuery {
+  from?: string;
+  to?: string;
+  status?: HistoryEntry['status'];
+  folder?: string;
+  media_id?: string;
+  limit?: number;
+}
+
+export function queryHistory(query: HistoryQuery) {
+  return invoke<HistoryEntry[]>('query_history', { query });
+}
+
+export function exportHistory(query: HistoryQuery, format: 'csv' | 'json', path: string) {
+  return invoke<number>('export_history', { query, format, path });
+}
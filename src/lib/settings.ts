@@ -0,0 +1,105 @@
+import { invoke } from '@tauri-apps/api/core';
+
+export type RuleKind = 'folder' | 'glob' | 'filename' | 'regex';
+
+export interface AutoTagRule {
+  kind: RuleKind;
+  pattern: string;
+  tags: string;
+  case_insensitive: boolean;
+}
+
+export type Auth =
+  | { kind: 'none' }
+  | { kind: 'bearer'; token: string }
+  | { kind: 'basic'; username: string; password: string }
+  | { kind: 'header'; name: string; value: string };
+
+export interface WatchTarget {
+  id: string;
+  folder: string;
+  server: string;
+  auto_tags: AutoTagRule[];
+  override_upload_date: boolean;
+  recursive: boolean;
+  enabled: boolean;
+  settle_ms: number;
+  auth: Auth;
+  catch_up: boolean;
+  watch_mode: 'native' | 'poll';
+  poll_interval_secs: number;
+}
+
+export interface RetryPolicy {
+  max_attempts: number;
+  base_delay_ms: number;
+  max_delay_ms: number;
+  jitter: number;
+  retry_statuses: number[];
+}
+
+export type LogLevel = 'error' | 'warn' | 'info' | 'debug' | 'trace';
+
+export interface Settings {
+  targets: WatchTarget[];
+  upload_workers: number;
+  retry: RetryPolicy;
+  connect_timeout_secs: number;
+  request_timeout_secs: number;
+  log_level: LogLevel;
+}
+
+export function defaultSettings(): Settings {
+  return {
+    targets: [],
+    upload_workers: 4,
+    retry: {
+      max_attempts: 5,
+      base_delay_ms: 500,
+      max_delay_ms: 30000,
+      jitter: 0.2,
+      retry_statuses: [429, 502, 503, 504]
+    },
+    connect_timeout_secs: 10,
+    request_timeout_secs: 30,
+    log_level: 'info'
+  };
+}
+
+export function loadSettings() {
+  return invoke<Settings>('load_settings');
+}
+
+export function saveSettings(settings: Settings) {
+  return invoke('save_settings', { settings });
+}
+
+export function emptyAuth(kind: Auth['kind']): Auth {
+  switch (kind) {
+    case 'bearer':
+      return { kind, token: '' };
+    case 'basic':
+      return { kind, username: '', password: '' };
+    case 'header':
+      return { kind, name: '', value: '' };
+    default:
+      return { kind: 'none' };
+  }
+}
+
+export function newTarget(): WatchTarget {
+  return {
+    id: crypto.randomUUID(),
+    folder: '',
+    server: '',
+    auto_tags: [],
+    override_upload_date: false,
+    recursive: false,
+    enabled: true,
+    settle_ms: 2000,
+    auth: { kind: 'none' },
+    catch_up: true,
+    watch_mode: 'native',
+    poll_interval_secs: 10
+  };
+}